@@ -12,10 +12,21 @@ use std::time::Instant;
 
 mod audio;
 mod bmp;
+mod files;
 mod input;
+mod log;
+mod work_queue;
 
 use super::reloader::*;
 use audio::Audio;
+use files::DataRoot;
+use input::MouseScale;
+use log::Logger;
+use work_queue::WorkQueue;
+
+// every allocation of the game, cached ground chunks included
+const PERMANENT_MEMORY_SIZE: usize = 512 * 1024 * 1024;
+const TRANSIENT_MEMORY_SIZE: usize = 256 * 1024 * 1024;
 
 fn new_texture(
     creator: &TextureCreator<WindowContext>, width: u32, height: u32,
@@ -28,9 +39,16 @@ fn new_texture(
 struct SdlHostApi<'a> {
     texture: Texture<'a>,
     audio: Audio,
+    log: Logger,
+    files: DataRoot,
+    work_queue: WorkQueue,
 }
 
 impl<'a> HostApi for SdlHostApi<'a> {
+    fn parallel_for(&self, count: usize, job: &(dyn Fn(usize) + Sync)) {
+        self.work_queue.parallel_for(count, job);
+    }
+
     fn update_canvas(&mut self, buffer: &[u8], pitch: usize) {
         self.texture.update(None, buffer, pitch).unwrap();
     }
@@ -40,7 +58,43 @@ impl<'a> HostApi for SdlHostApi<'a> {
     }
 
     fn load_bmp(&self, path: &str) -> Bitmap {
-        bmp::load_from_file(path)
+        let path = self.files.resolve(path).unwrap_or_else(|e| panic!("{}", e));
+        bmp::load_from_file(&path)
+    }
+
+    fn save_bmp(
+        &self, path: &str, buffer: &[u8], width: usize, height: usize, pitch: usize,
+    ) -> std::io::Result<()> {
+        let path = self.files.resolve(path)?;
+        bmp::save_to_file(&path, buffer, width, height, pitch)
+    }
+
+    fn read_file(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        self.files.read_file(path)
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> std::io::Result<()> {
+        self.files.write_file(path, contents)
+    }
+
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>> {
+        self.files.list_dir(path)
+    }
+
+    fn log_enabled(&self, level: LogLevel, tag: &str) -> bool {
+        self.log.enabled(level, tag)
+    }
+
+    fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        self.log.log(level, tag, msg);
+    }
+
+    fn set_log_level(&self, tag: &str, level: LogLevel) {
+        self.log.set_level(tag, level);
+    }
+
+    fn recent_logs(&self, count: usize) -> Vec<String> {
+        self.log.recent(count)
     }
 }
 
@@ -61,27 +115,43 @@ pub fn main(reloader: Receiver<()>) -> Result<(), String> {
     let (width, height) = canvas.window().size();
     let texture = new_texture(&texture_creator, width, height)?;
 
-    let mut host_api = SdlHostApi { texture, audio };
+    let mut host_api = SdlHostApi {
+        texture,
+        audio,
+        log: Logger::from_env(),
+        files: DataRoot::from_env(),
+        work_queue: WorkQueue::from_env(),
+    };
 
     let mut game = GameLib::new().unwrap();
     let mut api = game.api().unwrap();
-    let state = (api.init)(&host_api);
+    let mut memory = GameMemory::new(PERMANENT_MEMORY_SIZE, TRANSIENT_MEMORY_SIZE);
+    let state = (api.init)(&mut memory, &host_api);
 
     host_api.audio.toggle();
     let mut input = Input {
         new: Default::default(),
         old: Default::default(),
         time_per_frame: 1.0 / 60.0,
+        frame_time: 0.0,
     };
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut start_frame = Instant::now();
     'running: loop {
         if reloader.try_recv().is_ok() {
-            printn!("===== Reloading =====");
+            host_api.log(LogLevel::Info, "host", "===== Reloading =====");
             std::mem::drop(api);
             game = game.reload().unwrap();
             api = game.api().unwrap();
         }
+        let mouse_scale = {
+            // the texture is stretched into the top-left quarter of the window
+            let (window_width, window_height) = canvas.window().size();
+            MouseScale {
+                x: width as f32 / (window_width / 2) as f32,
+                y: height as f32 / (window_height / 2) as f32,
+            }
+        };
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
@@ -93,8 +163,8 @@ pub fn main(reloader: Receiver<()>) -> Result<(), String> {
                     keycode: Some(Keycode::Return),
                     ..
                 } => {
-                    println!("===== Restarting =====");
-                    (api.restart)(state);
+                    host_api.log(LogLevel::Info, "host", "===== Restarting =====");
+                    (api.restart)(state, &host_api);
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::Space),
@@ -102,35 +172,42 @@ pub fn main(reloader: Receiver<()>) -> Result<(), String> {
                 } => {
                     host_api.audio.toggle();
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    let level = host_api.log.cycle_default_level();
+                    let msg = format!("log level: {}", level);
+                    // the new level may filter Info out, this has to show anyway
+                    host_api.log.log_unfiltered(LogLevel::Info, "host", &msg);
+                }
                 Event::Window { win_event, .. } => match win_event {
                     WindowEvent::Resized(x, y) => {
-                        println!("resized {} {}", x, y);
+                        host_api.log(LogLevel::Debug, "host", &format!("resized {} {}", x, y));
                         let (x, y) = canvas.window().size();
-                        println!("logical {} {}", x, y);
+                        host_api.log(LogLevel::Debug, "host", &format!("logical {} {}", x, y));
                     }
                     _ => {}
                 },
                 _ => {}
             }
-            input::update(&mut input, &event);
+            input::update(&mut input, &event, mouse_scale);
         }
         // The rest of the game loop goes here...
         /// asdf
 
         (api.update)(state, &input, &mut host_api);
         canvas.clear();
-        let (width, height) = canvas.window().size();
+        let (window_width, window_height) = canvas.window().size();
         canvas.copy(
             &host_api.texture,
             None,
-            Rect::new(0, 0, width / 2, height / 2),
+            Rect::new(0, 0, window_width / 2, window_height / 2),
         )?;
         canvas.present();
         input::swap(&mut input);
 
-        if false {
-            dbg!(start_frame.elapsed());
-        }
+        input.frame_time = start_frame.elapsed().as_secs_f32();
         start_frame = Instant::now();
     }
     Ok(())
@@ -1,11 +1,13 @@
 use crate::host_api::*;
 use std::convert::TryInto;
 use std::fs;
+use std::io;
+use std::path::Path;
 
-pub fn load_from_file(path: &str) -> Bitmap {
+pub fn load_from_file(path: &Path) -> Bitmap {
     let pixels = fs::read(path)
         .or_else(|e| {
-            let msg = format!("While opening {}: {}", path, e);
+            let msg = format!("While opening {}: {}", path.display(), e);
             Err(msg)
         })
         .unwrap();
@@ -31,9 +33,7 @@ pub fn load_from_file(path: &str) -> Bitmap {
                 | (((color >> green_shift) & 0xFF) << 8)
                 | (((color >> blue_shift) & 0xFF) << 0);
 
-            for b in &good_color.to_le_bytes() {
-                result.push(*b);
-            }
+            result.extend_from_slice(&premultiply(good_color.to_le_bytes()));
         }
     }
     assert_eq!(result.len(), header.size_of_bitmap as usize);
@@ -47,6 +47,75 @@ pub fn load_from_file(path: &str) -> Bitmap {
     }
 }
 
+/// Writes a 24-bit uncompressed bmp. `buffer` is B G R A, top row first; the alpha channel is
+/// dropped since the screen is opaque anyway (and the magenta clear color has alpha 0)
+pub fn save_to_file(
+    path: &Path, buffer: &[u8], width: usize, height: usize, pitch: usize,
+) -> io::Result<()> {
+    let bytes_per_pixel = 3;
+    // bmp rows are padded to 4 bytes
+    let row_size = (width * bytes_per_pixel + 3) & !3;
+    let size_of_bitmap = row_size * height;
+    let header_size = std::mem::size_of::<BmpHeader>();
+
+    let header = BmpHeader {
+        file_type: 0x4D42, // "BM"
+        file_size: (header_size + size_of_bitmap) as u32,
+        reserved1: 0,
+        reserved2: 0,
+        bitmap_offset: header_size as u32,
+        // BITMAPINFOHEADER, the color masks are unused for 24-bit
+        size: 40,
+        width: width as i32,
+        height: height as i32,
+        planes: 1,
+        bits_per_pixel: (bytes_per_pixel * 8) as u16,
+        compression: 0,
+        size_of_bitmap: size_of_bitmap as u32,
+        horz_resolution: 2835,
+        vert_resolution: 2835,
+        colors_used: 0,
+        colors_important: 0,
+        red_mask: 0,
+        green_mask: 0,
+        blue_mask: 0,
+    };
+
+    let mut result = Vec::with_capacity(header_size + size_of_bitmap);
+    result.extend_from_slice(unsafe {
+        std::slice::from_raw_parts(&header as *const _ as *const u8, header_size)
+    });
+    // bottom row first
+    for y in (0..height).rev() {
+        let row = &buffer[y * pitch..y * pitch + width * 4];
+        for pixel in row.chunks_exact(4) {
+            result.extend_from_slice(&pixel[..3]);
+        }
+        result.resize(result.len() + row_size - width * bytes_per_pixel, 0);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, result)
+}
+
+/// The renderer blends premultiplied alpha in linear space, with gamma 2 standing in for sRGB:
+/// each color goes to linear, gets multiplied by alpha, and is encoded back
+fn premultiply(pixel: [u8; 4]) -> [u8; 4] {
+    let alpha = pixel[3] as f32 / 255.0;
+    let channel = |c: u8| {
+        let linear = (c as f32 / 255.0).powi(2);
+        (255.0 * (linear * alpha).sqrt()).round() as u8
+    };
+    [
+        channel(pixel[0]),
+        channel(pixel[1]),
+        channel(pixel[2]),
+        pixel[3],
+    ]
+}
+
 fn header(buf: &[u8]) -> BmpHeader {
     unsafe { std::ptr::read(buf.as_ptr() as *const _) }
     // let p = buf.as_ptr() as *const _;
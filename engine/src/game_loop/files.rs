@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Every path the game hands to the host is relative to this directory.
+/// Configured with `GAME_DATA_ROOT`, defaults to the working directory
+pub struct DataRoot {
+    root: PathBuf,
+}
+
+impl DataRoot {
+    pub fn from_env() -> Self {
+        let root = std::env::var("GAME_DATA_ROOT").unwrap_or_else(|_| ".".to_owned());
+        Self {
+            root: PathBuf::from(root),
+        }
+    }
+
+    /// Rejects absolute paths and `..`, so the game cannot escape the data root
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(path);
+        let escapes = relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            let msg = format!("{} is not relative to the data root", path);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    pub fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path)?)
+    }
+
+    /// Writes into a temporary sibling first, then renames it over `path`
+    pub fn write_file(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let path = self.resolve(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".tmp");
+        let tmp = path.with_file_name(tmp_name);
+        {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(contents)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path).or_else(|e| {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        })
+    }
+
+    /// File and directory names, sorted
+    pub fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut result = vec![];
+        for entry in fs::read_dir(self.resolve(path)?)? {
+            result.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        result.sort();
+        Ok(result)
+    }
+}
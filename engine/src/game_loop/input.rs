@@ -1,8 +1,16 @@
 use crate::host_api::*;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 
-pub fn update(input: &mut Input, event: &Event) {
+/// Converts window coordinates into `OffscreenBuffer` pixels
+#[derive(Copy, Clone, Debug)]
+pub struct MouseScale {
+    pub x: f32,
+    pub y: f32,
+}
+
+pub fn update(input: &mut Input, event: &Event, mouse_scale: MouseScale) {
     match event {
         Event::KeyUp { keycode, .. } => {
             if let Some(keycode) = keycode {
@@ -12,6 +20,21 @@ pub fn update(input: &mut Input, event: &Event) {
                     Keycode::Left => input.new.left = false,
                     Keycode::Right => input.new.right = false,
                     Keycode::S => input.new.sword = false,
+                    Keycode::F1 => input.new.debug = false,
+                    Keycode::Tab => input.new.next_entity = false,
+                    Keycode::F11 => input.new.record = false,
+                    Keycode::F12 => input.new.screenshot = false,
+                    Keycode::PageUp => input.new.zoom_in = false,
+                    Keycode::PageDown => input.new.zoom_out = false,
+                    Keycode::F2 => input.new.edit = false,
+                    Keycode::F5 => input.new.save = false,
+                    Keycode::Equals | Keycode::KpPlus => input.new.increase = false,
+                    Keycode::Minus | Keycode::KpMinus => input.new.decrease = false,
+                    Keycode::Home => input.new.floor_up = false,
+                    Keycode::End => input.new.floor_down = false,
+                    Keycode::F10 => input.new.log_tag = false,
+                    Keycode::F7 => input.new.log_less = false,
+                    Keycode::F8 => input.new.log_more = false,
                     _ => {}
                 }
             }
@@ -24,10 +47,45 @@ pub fn update(input: &mut Input, event: &Event) {
                     Keycode::Left => input.new.left = true,
                     Keycode::Right => input.new.right = true,
                     Keycode::S => input.new.sword = true,
+                    Keycode::F1 => input.new.debug = true,
+                    Keycode::Tab => input.new.next_entity = true,
+                    Keycode::F11 => input.new.record = true,
+                    Keycode::F12 => input.new.screenshot = true,
+                    Keycode::PageUp => input.new.zoom_in = true,
+                    Keycode::PageDown => input.new.zoom_out = true,
+                    Keycode::F2 => input.new.edit = true,
+                    Keycode::F5 => input.new.save = true,
+                    Keycode::Equals | Keycode::KpPlus => input.new.increase = true,
+                    Keycode::Minus | Keycode::KpMinus => input.new.decrease = true,
+                    Keycode::Home => input.new.floor_up = true,
+                    Keycode::End => input.new.floor_down = true,
+                    Keycode::F10 => input.new.log_tag = true,
+                    Keycode::F7 => input.new.log_less = true,
+                    Keycode::F8 => input.new.log_more = true,
                     _ => {}
                 }
             }
         }
+        Event::MouseMotion { x, y, .. } => {
+            input.new.mouse_x = (*x as f32 * mouse_scale.x) as i32;
+            input.new.mouse_y = (*y as f32 * mouse_scale.y) as i32;
+        }
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => input.new.mouse_left = true,
+        Event::MouseButtonUp {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => input.new.mouse_left = false,
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Right,
+            ..
+        } => input.new.mouse_right = true,
+        Event::MouseButtonUp {
+            mouse_btn: MouseButton::Right,
+            ..
+        } => input.new.mouse_right = false,
         _ => {}
     }
 }
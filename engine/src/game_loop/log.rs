@@ -0,0 +1,113 @@
+use crate::host_api::*;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+
+const RECENT_CAPACITY: usize = 256;
+
+/// Leveled, tagged logger backing `HostApi::log`.
+///
+/// Configured from the environment:
+/// - `GAME_LOG`: comma separated list of `level` (default) and `tag=level` entries,
+///   e.g. `warn,world=debug,sim=trace`
+/// - `GAME_LOG_FILE`: every kept line is also appended to this file
+///
+/// Kept lines go to stderr and to a ring buffer of the most recent ones
+pub struct Logger {
+    default_level: Cell<LogLevel>,
+    levels: RefCell<HashMap<String, LogLevel>>,
+    recent: RefCell<VecDeque<String>>,
+    file: RefCell<Option<File>>,
+}
+
+impl Logger {
+    pub fn from_env() -> Self {
+        let logger = Self {
+            default_level: Cell::new(LogLevel::Info),
+            levels: RefCell::new(HashMap::new()),
+            recent: RefCell::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+            file: RefCell::new(None),
+        };
+
+        if let Ok(spec) = std::env::var("GAME_LOG") {
+            if let Err(e) = logger.configure(&spec) {
+                logger.log(LogLevel::Warn, "host", &format!("GAME_LOG: {}", e));
+            }
+        }
+        if let Ok(path) = std::env::var("GAME_LOG_FILE") {
+            match File::create(&path) {
+                Ok(file) => *logger.file.borrow_mut() = Some(file),
+                Err(e) => {
+                    let msg = format!("GAME_LOG_FILE {}: {}", path, e);
+                    logger.log(LogLevel::Warn, "host", &msg);
+                }
+            }
+        }
+        logger
+    }
+
+    fn configure(&self, spec: &str) -> Result<(), String> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.find('=') {
+                Some(pos) => self.set_level(&entry[..pos], entry[pos + 1..].parse()?),
+                None => self.default_level.set(entry.parse()?),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn enabled(&self, level: LogLevel, tag: &str) -> bool {
+        let max = self
+            .levels
+            .borrow()
+            .get(tag)
+            .copied()
+            .unwrap_or_else(|| self.default_level.get());
+        level <= max
+    }
+
+    pub fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        if self.enabled(level, tag) {
+            self.log_unfiltered(level, tag, msg);
+        }
+    }
+
+    /// Kept whatever the levels, for feedback about the logger itself
+    pub fn log_unfiltered(&self, level: LogLevel, tag: &str, msg: &str) {
+        let line = format!("[{:<5}] {}: {}", level, tag, msg);
+        eprintln!("{}", line);
+        if let Some(file) = self.file.borrow_mut().as_mut() {
+            // a failing log file should not take the game down
+            let _ = writeln!(file, "{}", line);
+        }
+        let mut recent = self.recent.borrow_mut();
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+
+    pub fn set_level(&self, tag: &str, level: LogLevel) {
+        self.levels.borrow_mut().insert(tag.to_owned(), level);
+    }
+
+    /// Cycles the default level: error -> warn -> info -> debug -> trace -> error
+    pub fn cycle_default_level(&self) -> LogLevel {
+        let next = match self.default_level.get() {
+            LogLevel::Error => LogLevel::Warn,
+            LogLevel::Warn => LogLevel::Info,
+            LogLevel::Info => LogLevel::Debug,
+            LogLevel::Debug => LogLevel::Trace,
+            LogLevel::Trace => LogLevel::Error,
+        };
+        self.default_level.set(next);
+        next
+    }
+
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let recent = self.recent.borrow();
+        let skip = recent.len().saturating_sub(count);
+        recent.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,142 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = &'static (dyn Fn(usize) + Sync);
+
+struct State {
+    job: Option<Job>,
+    next: usize,
+    count: usize,
+    completed: usize,
+    panicked: bool,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    work_available: Condvar,
+    work_done: Condvar,
+}
+
+/// Thread pool backing `HostApi::parallel_for`. The calling thread works on the job too.
+/// Worker count comes from `GAME_WORKER_THREADS`, defaults to 3
+pub struct WorkQueue {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkQueue {
+    pub fn from_env() -> Self {
+        let count = std::env::var("GAME_WORKER_THREADS")
+            .ok()
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(3);
+        Self::new(count)
+    }
+
+    pub fn new(worker_count: usize) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                job: None,
+                next: 0,
+                count: 0,
+                completed: 0,
+                panicked: false,
+                shutdown: false,
+            }),
+            work_available: Condvar::new(),
+            work_done: Condvar::new(),
+        });
+        let workers = (0..worker_count)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("worker {}", i))
+                    .spawn(move || worker(&shared))
+                    .expect("failed to spawn worker")
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    /// Runs `job(0..count)` across the pool and returns once every index is done
+    pub fn parallel_for(&self, count: usize, job: &(dyn Fn(usize) + Sync)) {
+        // `job` only has to outlive this call: we do not return before every index completed
+        let job: Job = unsafe { std::mem::transmute(job) };
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            assert!(state.job.is_none(), "parallel_for is not reentrant");
+            state.job = Some(job);
+            state.next = 0;
+            state.count = count;
+            state.completed = 0;
+            state.panicked = false;
+        }
+        self.shared.work_available.notify_all();
+
+        while run_next(&self.shared) {}
+
+        let panicked = {
+            let mut state = self.shared.state.lock().unwrap();
+            while state.completed < state.count {
+                state = self.shared.work_done.wait(state).unwrap();
+            }
+            state.job = None;
+            state.panicked
+        };
+        // outside of the lock, so the mutex is not poisoned
+        if panicked {
+            panic!("a parallel_for job panicked");
+        }
+    }
+}
+
+impl Drop for WorkQueue {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.work_available.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(shared: &Shared) {
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            while !state.shutdown && (state.job.is_none() || state.next >= state.count) {
+                state = shared.work_available.wait(state).unwrap();
+            }
+            if state.shutdown {
+                return;
+            }
+        }
+        run_next(shared);
+    }
+}
+
+/// Runs one pending index, returns `false` if there was none
+fn run_next(shared: &Shared) -> bool {
+    let (job, idx) = {
+        let mut state = shared.state.lock().unwrap();
+        match state.job {
+            Some(job) if state.next < state.count => {
+                state.next += 1;
+                (job, state.next - 1)
+            }
+            _ => return false,
+        }
+    };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| job(idx)));
+
+    let mut state = shared.state.lock().unwrap();
+    state.panicked |= result.is_err();
+    state.completed += 1;
+    if state.completed == state.count {
+        shared.work_done.notify_all();
+    }
+    true
+}
@@ -69,7 +69,7 @@ pub struct GameState {
 
 pub struct GameApi<'lib> {
     /// Called on game start
-    pub init: lib::Symbol<'lib, fn(&dyn HostApi) -> *mut GameState>,
+    pub init: lib::Symbol<'lib, fn(&mut GameMemory, &dyn HostApi) -> *mut GameState>,
 
     /// Called on game loop. Returns `true` if the game continues running
     pub update: lib::Symbol<'lib, fn(*mut GameState, &Input, &mut dyn HostApi) -> bool>,
@@ -80,5 +80,5 @@ pub struct GameApi<'lib> {
     // pub unload: lib::Symbol<'lib, fn(*mut GameState)>,
 
     // Called on game reload
-    pub restart: lib::Symbol<'lib, fn(*mut GameState)>,
+    pub restart: lib::Symbol<'lib, fn(*mut GameState, &dyn HostApi)>,
 }
@@ -0,0 +1,252 @@
+use super::*;
+
+/// Something gameplay reacts to when a frame starts playing
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AnimationEvent {
+    Footstep,
+    /// The moment an attack lands (or a sword leaves the hand)
+    Strike,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    /// Nudges the bitmaps, in bitmap pixels (y down)
+    pub offset: V2,
+    /// In seconds
+    pub duration: f32,
+    pub event: Option<AnimationEvent>,
+}
+
+impl Frame {
+    pub fn new(duration: f32) -> Self {
+        assert!(duration > 0.0);
+        Self {
+            offset: V2::default(),
+            duration,
+            event: None,
+        }
+    }
+
+    pub fn nudged(mut self, offset: V2) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_event(mut self, event: AnimationEvent) -> Self {
+        self.event = Some(event);
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Playback {
+    Loop,
+    /// Holds the last frame once done
+    Once,
+}
+
+pub struct Clip {
+    frames: Vec<Frame>,
+    playback: Playback,
+}
+
+impl Clip {
+    pub fn new(playback: Playback, frames: Vec<Frame>) -> Self {
+        assert!(!frames.is_empty());
+        Self { frames, playback }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClipId {
+    Idle,
+    Walk,
+    Attack,
+}
+
+impl Default for ClipId {
+    fn default() -> Self {
+        ClipId::Idle
+    }
+}
+
+/// Every clip an entity kind can play
+pub struct Animations {
+    idle: Clip,
+    walk: Clip,
+    attack: Clip,
+}
+
+impl Animations {
+    pub fn new(idle: Clip, walk: Clip, attack: Clip) -> Self {
+        Self { idle, walk, attack }
+    }
+
+    pub fn clip(&self, id: ClipId) -> &Clip {
+        match id {
+            ClipId::Idle => &self.idle,
+            ClipId::Walk => &self.walk,
+            ClipId::Attack => &self.attack,
+        }
+    }
+}
+
+/// Where an entity is in its current clip. Lives in `SimEntity`, so it's stored with the
+/// rest of the entity between frames
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AnimationState {
+    clip: ClipId,
+    frame: usize,
+    /// Time spent in `frame`
+    t: f32,
+    /// The first frame's event has fired
+    started: bool,
+    finished: bool,
+}
+
+impl AnimationState {
+    pub fn clip(&self) -> ClipId {
+        self.clip
+    }
+
+    /// Only `Once` clips finish
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Switches to `clip` from its start. Playing the clip already playing does nothing,
+    /// unless it finished
+    pub fn play(&mut self, clip: ClipId) {
+        if clip != self.clip || self.finished {
+            *self = Self {
+                clip,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Moves `dt` seconds forward, calling `on_event` for every frame started on the way
+    pub fn advance(
+        &mut self, animations: &Animations, dt: f32, mut on_event: impl FnMut(AnimationEvent),
+    ) {
+        let clip = animations.clip(self.clip);
+        if !self.started {
+            self.started = true;
+            if let Some(event) = clip.frames[0].event {
+                on_event(event);
+            }
+        }
+        if self.finished {
+            return;
+        }
+        self.t += dt;
+        loop {
+            let duration = clip.frames[self.frame].duration;
+            if self.t < duration {
+                break;
+            }
+            if self.frame + 1 < clip.frames.len() {
+                self.frame += 1;
+            } else if clip.playback == Playback::Loop {
+                self.frame = 0;
+            } else {
+                self.finished = true;
+                self.t = duration;
+                break;
+            }
+            self.t -= duration;
+            if let Some(event) = clip.frames[self.frame].event {
+                on_event(event);
+            }
+        }
+    }
+
+    pub fn frame<'a>(&self, animations: &'a Animations) -> &'a Frame {
+        &animations.clip(self.clip).frames[self.frame]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animations() -> Animations {
+        let idle = Clip::new(Playback::Loop, vec![Frame::new(1.0)]);
+        let walk = Clip::new(
+            Playback::Loop,
+            vec![
+                Frame::new(0.25).with_event(AnimationEvent::Footstep),
+                Frame::new(0.25),
+            ],
+        );
+        let attack = Clip::new(
+            Playback::Once,
+            vec![
+                Frame::new(0.1),
+                Frame::new(0.1).with_event(AnimationEvent::Strike),
+                Frame::new(0.1),
+            ],
+        );
+        Animations::new(idle, walk, attack)
+    }
+
+    fn advance(
+        state: &mut AnimationState, animations: &Animations, dt: f32,
+    ) -> Vec<AnimationEvent> {
+        let mut events = vec![];
+        state.advance(animations, dt, |event| events.push(event));
+        events
+    }
+
+    #[test]
+    fn loops_and_fires_on_frame_start() {
+        let animations = animations();
+        let mut state = AnimationState::default();
+        state.play(ClipId::Walk);
+        assert_eq!(
+            advance(&mut state, &animations, 0.1),
+            [AnimationEvent::Footstep]
+        );
+        assert_eq!(state.frame, 0);
+        assert_eq!(advance(&mut state, &animations, 0.2), []);
+        assert_eq!(state.frame, 1);
+        // a long step wraps around more than once
+        let events = advance(&mut state, &animations, 1.0);
+        assert_eq!(events, [AnimationEvent::Footstep; 2]);
+        assert_eq!(state.frame, 1);
+        assert!(!state.finished());
+    }
+
+    #[test]
+    fn once_holds_the_last_frame() {
+        let animations = animations();
+        let mut state = AnimationState::default();
+        state.play(ClipId::Attack);
+        assert_eq!(
+            advance(&mut state, &animations, 0.15),
+            [AnimationEvent::Strike]
+        );
+        assert!(!state.finished());
+        assert_eq!(advance(&mut state, &animations, 10.0), []);
+        assert!(state.finished());
+        assert_eq!(state.frame, 2);
+        assert_eq!(advance(&mut state, &animations, 10.0), []);
+    }
+
+    #[test]
+    fn play_restarts_only_new_or_finished_clips() {
+        let animations = animations();
+        let mut state = AnimationState::default();
+        state.play(ClipId::Walk);
+        advance(&mut state, &animations, 0.3);
+        state.play(ClipId::Walk);
+        assert_eq!(state.frame, 1);
+
+        state.play(ClipId::Attack);
+        advance(&mut state, &animations, 1.0);
+        assert!(state.finished());
+        state.play(ClipId::Attack);
+        assert!(!state.finished());
+        assert_eq!(state.frame, 0);
+    }
+}
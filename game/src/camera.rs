@@ -0,0 +1,75 @@
+use super::*;
+
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+/// Zoom doubles (or halves) every second the key is held
+const ZOOM_PER_SECOND: f32 = 2.0;
+
+/// Where the screen looks and how sim space maps onto it. Sim space is in meters with y up,
+/// relative to `p`; the screen is in `OffscreenBuffer` pixels with y down.
+/// Bitmaps are authored at zoom 1
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub p: WorldPosition,
+    pub zoom: f32,
+    /// How much bigger things get per meter above the ground, as if the camera was
+    /// looking down on them from not too far. 0 turns it off
+    pub perspective: f32,
+    pixels_per_meter: f32,
+    screen_size: V2,
+}
+
+impl Camera {
+    pub fn new(p: WorldPosition, pixels_per_meter: f32, screen_size: V2) -> Self {
+        Self {
+            p,
+            zoom: 1.0,
+            perspective: 0.1,
+            pixels_per_meter,
+            screen_size,
+        }
+    }
+
+    pub fn meters_to_pixels(&self) -> f32 {
+        self.pixels_per_meter * self.zoom
+    }
+
+    pub fn screen_center(&self) -> V2 {
+        0.5 * self.screen_size
+    }
+
+    /// Screen position of the sim space point `p`
+    pub fn to_screen(self, p: V2) -> V2 {
+        self.screen_center() + self.to_screen_offset(p)
+    }
+
+    /// Screen displacement for the sim space displacement `d`
+    pub fn to_screen_offset(self, d: V2) -> V2 {
+        self.meters_to_pixels() * V2::new(d.x(), -d.y())
+    }
+
+    /// Screen scale of something `z` meters above the ground, zoom included
+    pub fn scale_at(&self, z: f32) -> f32 {
+        self.zoom * (1.0 + self.perspective * z)
+    }
+
+    /// Screen displacement lifting something `z` meters above the ground
+    pub fn lift(&self, z: f32) -> V2 {
+        V2::new(0.0, -self.meters_to_pixels() * z)
+    }
+
+    /// Zooms around the screen center while the zoom keys are held
+    pub fn update_zoom(&mut self, input: &Input) {
+        let mut direction = 0.0;
+        if input.new.zoom_in {
+            direction += 1.0;
+        }
+        if input.new.zoom_out {
+            direction -= 1.0;
+        }
+        if direction != 0.0 {
+            let factor = ZOOM_PER_SECOND.powf(direction * input.time_per_frame);
+            self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// F12 saves the current frame, F11 starts/stops dumping every frame into a numbered sequence
+#[derive(Default)]
+pub struct Capture {
+    sequence: Option<Sequence>,
+}
+
+struct Sequence {
+    dir: String,
+    frame: usize,
+}
+
+impl Capture {
+    pub fn update(&mut self, input: &Input, buffer: &OffscreenBuffer, host_api: &dyn HostApi) {
+        if input.pressed(|i| i.record) {
+            self.sequence = match self.sequence {
+                Some(_) => None,
+                None => Some(Sequence {
+                    dir: format!("captures/sequence_{}", timestamp()),
+                    frame: 0,
+                }),
+            };
+        }
+
+        if input.pressed(|i| i.screenshot) {
+            let path = format!("captures/screenshot_{}.bmp", timestamp());
+            save(buffer, &path, host_api);
+        }
+
+        if let Some(sequence) = &mut self.sequence {
+            let path = format!("{}/frame_{:05}.bmp", sequence.dir, sequence.frame);
+            save(buffer, &path, host_api);
+            sequence.frame += 1;
+        }
+    }
+}
+
+fn save(buffer: &OffscreenBuffer, path: &str, host_api: &dyn HostApi) {
+    let result = host_api.save_bmp(
+        path,
+        &buffer.buffer,
+        buffer.width,
+        buffer.height,
+        buffer.pitch(),
+    );
+    match result {
+        Ok(()) => info!(host_api, "capture", "saved {}", path),
+        Err(e) => error!(host_api, "capture", "failed to save {}: {}", path, e),
+    }
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+}
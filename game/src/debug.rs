@@ -0,0 +1,218 @@
+use super::*;
+use render::{text_size, ClipRect};
+
+const TEXT_SCALE: f32 = 2.0;
+const LOG_LINES: usize = 8;
+/// Subsystems whose log level the overlay can change, F10 picks one, F8 and F7 log more or
+/// less of it
+const LOG_TAGS: [&str; 4] = ["sim", "world", "editor", "capture"];
+
+/// In-game debug overlay, toggled with F1.
+/// The inspected entity is the hovered one, or the one picked with a click/Tab
+#[derive(Default)]
+pub struct DebugState {
+    pub enabled: bool,
+    selected: Option<StorageIdx>,
+    log_tag: usize,
+    /// Only the levels set from the overlay, the host doesn't tell the others
+    log_levels: [Option<LogLevel>; LOG_TAGS.len()],
+}
+
+/// Everything the overlay needs to map sim space into the screen
+pub struct DebugView {
+    pub camera: Camera,
+    pub chunk_side: f32,
+}
+
+impl DebugView {
+    fn to_screen(&self, p: V2) -> V2 {
+        self.camera.to_screen(p)
+    }
+
+    fn entity_rect(&self, entity: &SimEntity) -> Rect2 {
+        let dim = self.camera.meters_to_pixels() * V2::new(entity.width, entity.height);
+        Rect2::new_center_dim(self.to_screen(entity.p), dim)
+    }
+}
+
+impl DebugState {
+    pub fn toggle(&mut self, input: &Input) {
+        if input.pressed(|i| i.debug) {
+            self.enabled = !self.enabled;
+        }
+    }
+
+    pub fn draw(
+        &mut self, buffer: &mut OffscreenBuffer, input: &Input, sim_region: &SimRegion,
+        view: &DebugView, host_api: &dyn HostApi,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let mouse = V2::new(input.new.mouse_x as f32, input.new.mouse_y as f32);
+        let hovered = sim_region
+            .entities
+            .values()
+            .filter(|e| e.spatial)
+            .find(|e| view.entity_rect(e).contains(mouse))
+            .map(|e| e.idx);
+        if input.pressed(|i| i.mouse_left) {
+            self.selected = hovered;
+        }
+        if input.pressed(|i| i.next_entity) {
+            self.selected = self.next_entity(sim_region);
+        }
+        if self
+            .selected
+            .map_or(false, |idx| !sim_region.entities.contains_key(&idx))
+        {
+            self.selected = None;
+        }
+
+        self.update_log_level(input, host_api);
+
+        self.draw_chunks(buffer, sim_region, view);
+        debug_draw::flush(buffer, &view.camera);
+        let bounds = sim_region.bounds();
+        buffer.render_outline(
+            view.to_screen(V2::new(bounds.min().x(), bounds.max().y())),
+            view.to_screen(V2::new(bounds.max().x(), bounds.min().y())),
+            Color::green(),
+        );
+        let bounds = sim_region.updatable_bounds();
+        buffer.render_outline(
+            view.to_screen(V2::new(bounds.min().x(), bounds.max().y())),
+            view.to_screen(V2::new(bounds.max().x(), bounds.min().y())),
+            Color::yellow(),
+        );
+
+        let updatable = sim_region.entities.values().filter(|e| e.updatable).count();
+        let stats = format!(
+            "frame: {:.2} ms\nsimmed: {} ({} updatable)\nchunk: {} {} {}\nzoom: {:.2}\nlog {}: {}",
+            1000.0 * input.frame_time,
+            sim_region.entities.len(),
+            updatable,
+            sim_region.origin.abs.x,
+            sim_region.origin.abs.y,
+            sim_region.origin.abs.z,
+            view.camera.zoom,
+            LOG_TAGS[self.log_tag],
+            self.log_levels[self.log_tag].map_or("-".to_owned(), |l| l.to_string()),
+        );
+        panel(buffer, V2::new(4.0, 4.0), &stats);
+
+        if let Some(entity) = self
+            .selected
+            .or(hovered)
+            .and_then(|idx| sim_region.entities.get(&idx))
+        {
+            let rect = view.entity_rect(entity);
+            buffer.render_outline(rect.min(), rect.max(), Color::white());
+            let panel_p = V2::new(4.0, 4.0 + text_size(&stats, TEXT_SCALE).y() + 12.0);
+            panel(buffer, panel_p, &describe(entity));
+        }
+
+        let logs = host_api.recent_logs(LOG_LINES).join("\n");
+        if !logs.is_empty() {
+            let size = text_size(&logs, TEXT_SCALE);
+            let p = V2::new(4.0, buffer.height as f32 - size.y() - 12.0);
+            panel(buffer, p, &logs);
+        }
+    }
+
+    fn update_log_level(&mut self, input: &Input, host_api: &dyn HostApi) {
+        if input.pressed(|i| i.log_tag) {
+            self.log_tag = (self.log_tag + 1) % LOG_TAGS.len();
+        }
+        let level = self.log_levels[self.log_tag].unwrap_or(LogLevel::Info);
+        let level = if input.pressed(|i| i.log_more) {
+            match level {
+                LogLevel::Error => LogLevel::Warn,
+                LogLevel::Warn => LogLevel::Info,
+                LogLevel::Info => LogLevel::Debug,
+                _ => LogLevel::Trace,
+            }
+        } else if input.pressed(|i| i.log_less) {
+            match level {
+                LogLevel::Trace => LogLevel::Debug,
+                LogLevel::Debug => LogLevel::Info,
+                LogLevel::Info => LogLevel::Warn,
+                _ => LogLevel::Error,
+            }
+        } else {
+            return;
+        };
+        self.log_levels[self.log_tag] = Some(level);
+        host_api.set_log_level(LOG_TAGS[self.log_tag], level);
+    }
+
+    fn next_entity(&self, sim_region: &SimRegion) -> Option<StorageIdx> {
+        let mut spatial = sim_region
+            .entities
+            .values()
+            .filter(|e| e.spatial)
+            .map(|e| e.idx);
+        match self.selected {
+            Some(selected) => spatial
+                .clone()
+                .find(|idx| *idx > selected)
+                .or_else(|| spatial.next()),
+            None => spatial.next(),
+        }
+    }
+
+    fn draw_chunks(&self, buffer: &mut OffscreenBuffer, sim_region: &SimRegion, view: &DebugView) {
+        // origin offset is relative to the chunk center
+        let offset = sim_region.origin.offset;
+        let bounds = sim_region.bounds();
+        let first_x = ((bounds.min().x() + offset.x()) / view.chunk_side - 0.5).floor() as i32;
+        let last_x = ((bounds.max().x() + offset.x()) / view.chunk_side - 0.5).ceil() as i32;
+        for k in first_x..=last_x {
+            let x = (k as f32 + 0.5) * view.chunk_side - offset.x();
+            let min = view.to_screen(V2::new(x, bounds.max().y()));
+            let max = view.to_screen(V2::new(x, bounds.min().y()));
+            buffer.render_rectangle(min, max + V2::new(1.0, 0.0), Color::magenta());
+        }
+        let first_y = ((bounds.min().y() + offset.y()) / view.chunk_side - 0.5).floor() as i32;
+        let last_y = ((bounds.max().y() + offset.y()) / view.chunk_side - 0.5).ceil() as i32;
+        for k in first_y..=last_y {
+            let y = (k as f32 + 0.5) * view.chunk_side - offset.y();
+            let min = view.to_screen(V2::new(bounds.min().x(), y));
+            let max = view.to_screen(V2::new(bounds.max().x(), y));
+            buffer.render_rectangle(min, max + V2::new(0.0, 1.0), Color::magenta());
+        }
+    }
+}
+
+fn describe(entity: &SimEntity) -> String {
+    let hit_points: Vec<String> = entity
+        .hit_points
+        .iter()
+        .map(|h| h.filled().to_string())
+        .collect();
+    format!(
+        "entity {}\nkind: {:?}\np: {:.2} {:.2}\ndp: {:.2} {:.2}\ncollides: {}\nhit points: [{}]\ndistance remaining: {:.2}",
+        entity.idx.index(),
+        entity.kind,
+        entity.p.x(),
+        entity.p.y(),
+        entity.dp.x(),
+        entity.dp.y(),
+        entity.collides,
+        hit_points.join(" "),
+        entity.distance_remaining,
+    )
+}
+
+/// At most half the screen wide, longer lines are cut
+pub fn panel(buffer: &mut OffscreenBuffer, p: V2, text: &str) {
+    let padding = V2::new(4.0, 4.0);
+    let size = text_size(text, TEXT_SCALE);
+    let size = V2::new(size.x().min(0.5 * buffer.width as f32), size.y());
+    let mut target = buffer.target();
+    target.render_rectangle(p, p + size + 2.0 * padding, Color::black().with_alpha(0.75));
+    target.push_clip(ClipRect::covering(p + padding, p + padding + size));
+    target.render_text(text, p + padding, TEXT_SCALE, Color::white());
+    target.pop_clip();
+}
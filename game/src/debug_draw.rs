@@ -0,0 +1,88 @@
+//! Shapes any game code can queue while simulating, drawn over the frame by the debug overlay.
+//! Positions are in sim space meters; nothing is queued while the overlay is off:
+//! `debug_draw::arrow(entity.p, entity.p + entity.dp, Color::green())`
+
+use super::*;
+use std::cell::RefCell;
+
+#[derive(Copy, Clone, Debug)]
+enum Shape {
+    Line { a: V2, b: V2 },
+    Arrow { from: V2, to: V2 },
+    Rect { center: V2, dim: V2 },
+    Circle { center: V2, radius: f32 },
+}
+
+struct Queue {
+    enabled: bool,
+    shapes: Vec<(Shape, Color)>,
+}
+
+// only the thread running `game_update` queues and draws
+thread_local! {
+    static QUEUE: RefCell<Queue> = RefCell::new(Queue {
+        enabled: false,
+        shapes: Vec::new(),
+    });
+}
+
+fn push(shape: Shape, color: Color) {
+    QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        if queue.enabled {
+            queue.shapes.push((shape, color));
+        }
+    });
+}
+
+/// Drops whatever the last frame left, and starts queueing if `enabled`
+pub fn begin(enabled: bool) {
+    QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        queue.enabled = enabled;
+        queue.shapes.clear();
+    });
+}
+
+pub fn line(a: V2, b: V2, color: Color) {
+    push(Shape::Line { a, b }, color);
+}
+
+pub fn arrow(from: V2, to: V2, color: Color) {
+    push(Shape::Arrow { from, to }, color);
+}
+
+/// Outline only
+pub fn rect(center: V2, dim: V2, color: Color) {
+    push(Shape::Rect { center, dim }, color);
+}
+
+pub fn circle(center: V2, radius: f32, color: Color) {
+    push(Shape::Circle { center, radius }, color);
+}
+
+/// Draws and drops everything queued
+pub fn flush(buffer: &mut OffscreenBuffer, camera: &Camera) {
+    let shapes = QUEUE.with(|queue| std::mem::take(&mut queue.borrow_mut().shapes));
+    for (shape, color) in shapes {
+        match shape {
+            Shape::Line { a, b } => {
+                buffer.render_line(camera.to_screen(a), camera.to_screen(b), color)
+            }
+            Shape::Arrow { from, to } => {
+                buffer.render_arrow(camera.to_screen(from), camera.to_screen(to), color)
+            }
+            Shape::Rect { center, dim } => {
+                // sim space y goes up
+                let half = 0.5 * V2::new(dim.x(), -dim.y());
+                let min = camera.to_screen(center - half);
+                let max = camera.to_screen(center + half);
+                buffer.render_outline(min, max, color);
+            }
+            Shape::Circle { center, radius } => {
+                let radius = camera.meters_to_pixels() * radius;
+                buffer.render_circle(camera.to_screen(center), radius, color)
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Lays out a dungeon from a seed: floors of screen sized rooms on a grid, joined by doors
+//! within a floor and by stairs or ladders between floors, with monsters and familiars
+//! scattered around. The same seed always gives the same dungeon, `World::generate` builds it
+//! `Dungeon::generate(seed, &DungeonSpec::default())`
+
+use super::*;
+use std::collections::BTreeMap;
+
+/// In tiles, walls included
+pub const ROOM_WIDTH: i32 = 17;
+pub const ROOM_HEIGHT: i32 = 9;
+
+/// Room grid position: `x`, `y` in rooms, `z` in floors
+pub type Cell = (i32, i32, i32);
+/// Absolute tile, `z` being the floor
+pub type TilePos = (i32, i32, i32);
+
+#[derive(Copy, Clone, Debug)]
+pub struct DungeonSpec {
+    pub floors: i32,
+    pub rooms_per_floor: usize,
+    /// Of the room grid of every floor, in rooms
+    pub grid: (i32, i32),
+    /// Chance for two neighbors that aren't joined yet to get a door anyway, making loops
+    pub extra_doors: f32,
+    /// Chance for every room but the first to get a monster
+    pub monsters: f32,
+    pub familiars: f32,
+}
+
+impl Default for DungeonSpec {
+    fn default() -> Self {
+        Self {
+            floors: 2,
+            rooms_per_floor: 6,
+            grid: (4, 3),
+            extra_doors: 0.2,
+            monsters: 0.5,
+            familiars: 0.2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Side {
+    West,
+    East,
+    South,
+    North,
+}
+
+impl Side {
+    const ALL: [Side; 4] = [Side::West, Side::East, Side::South, Side::North];
+
+    fn step(self, (x, y, z): Cell) -> Cell {
+        match self {
+            Side::West => (x - 1, y, z),
+            Side::East => (x + 1, y, z),
+            Side::South => (x, y - 1, z),
+            Side::North => (x, y + 1, z),
+        }
+    }
+
+    fn opposite(self) -> Side {
+        match self {
+            Side::West => Side::East,
+            Side::East => Side::West,
+            Side::South => Side::North,
+            Side::North => Side::South,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomPlan {
+    pub cell: Cell,
+    /// Per `Side`, how far along its wall the door is, in tiles from the room's corner
+    pub doors: [Option<i32>; 4],
+    /// Open to the sky, or lit by a torch
+    pub outdoors: bool,
+    /// Index of the torch color
+    pub torch: usize,
+}
+
+impl RoomPlan {
+    fn new(cell: Cell) -> Self {
+        Self {
+            cell,
+            doors: [None; 4],
+            outdoors: false,
+            torch: 0,
+        }
+    }
+
+    /// Tile of the room's bottom-left corner
+    pub fn min(&self) -> TilePos {
+        let (x, y, z) = self.cell;
+        (x * ROOM_WIDTH, y * ROOM_HEIGHT, z)
+    }
+
+    pub fn door(&self, side: Side) -> Option<i32> {
+        self.doors[side as usize]
+    }
+
+    /// Every wall tile, which is the whole border but the doors
+    pub fn walls(&self) -> Vec<TilePos> {
+        let (min_x, min_y, z) = self.min();
+        let mut result = vec![];
+        for y in 0..ROOM_HEIGHT {
+            for x in 0..ROOM_WIDTH {
+                let side = if x == 0 {
+                    Side::West
+                } else if x == ROOM_WIDTH - 1 {
+                    Side::East
+                } else if y == 0 {
+                    Side::South
+                } else if y == ROOM_HEIGHT - 1 {
+                    Side::North
+                } else {
+                    continue;
+                };
+                let along = match side {
+                    Side::West | Side::East => y,
+                    Side::South | Side::North => x,
+                };
+                if self.door(side) != Some(along) {
+                    result.push((min_x + x, min_y + y, z));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dungeon {
+    pub seed: u32,
+    /// Floor by floor, the first one holds the player
+    pub rooms: Vec<RoomPlan>,
+    /// Both ends of every flight and ladder
+    pub stairs: Vec<Stairs>,
+    pub player: TilePos,
+    pub monsters: Vec<TilePos>,
+    pub familiars: Vec<TilePos>,
+}
+
+impl Dungeon {
+    pub fn generate(seed: u32, spec: &DungeonSpec) -> Self {
+        let mut random = Random::new(seed);
+        let mut rooms: Vec<RoomPlan> = vec![];
+        let mut cells: BTreeMap<Cell, usize> = BTreeMap::new();
+        let mut stairs = vec![];
+        let player = (ROOM_WIDTH / 2, ROOM_HEIGHT / 2);
+        // kept free of anything spawned
+        let mut taken: Vec<TilePos> = vec![];
+
+        let mut floor_below = 0..0;
+        for z in 0..spec.floors {
+            let first_room = rooms.len();
+            let start = if z == 0 {
+                let (width, height) = spec.grid;
+                (
+                    random.below(width as usize) as i32,
+                    random.below(height as usize) as i32,
+                    0,
+                )
+            } else {
+                // straight above one of the rooms below
+                let below = floor_below.start + random.below(floor_below.len());
+                let (x, y, _) = rooms[below].cell;
+                (x, y, z)
+            };
+            cells.insert(start, rooms.len());
+            rooms.push(RoomPlan::new(start));
+
+            // grows from random rooms of the floor into free neighbors
+            let mut attempts = 0;
+            while rooms.len() - first_room < spec.rooms_per_floor && attempts < 1000 {
+                attempts += 1;
+                let from = first_room + random.below(rooms.len() - first_room);
+                let side = Side::ALL[random.below(4)];
+                let cell = side.step(rooms[from].cell);
+                if !in_grid(cell, spec.grid) || cells.contains_key(&cell) {
+                    continue;
+                }
+                cells.insert(cell, rooms.len());
+                rooms.push(RoomPlan::new(cell));
+                let to = rooms.len() - 1;
+                add_door(&mut rooms, from, to, side, &mut random);
+            }
+
+            for from in first_room..rooms.len() {
+                for &side in &[Side::East, Side::North] {
+                    let to = match cells.get(&side.step(rooms[from].cell)) {
+                        Some(&to) => to,
+                        None => continue,
+                    };
+                    if rooms[from].door(side).is_none() && random.unilateral() < spec.extra_doors {
+                        add_door(&mut rooms, from, to, side, &mut random);
+                    }
+                }
+            }
+
+            if z > 0 {
+                let (x, y, _) = rooms[first_room].min();
+                // clear of the walls, the doors in them, and where the player starts
+                let mut along = 2 + random.below((ROOM_WIDTH - 5) as usize) as i32;
+                if along >= player.0 {
+                    along += 1;
+                }
+                let kind = if random.unilateral() < 0.3 {
+                    StairsKind::Ladder
+                } else {
+                    StairsKind::Stairs
+                };
+                let length = if kind == StairsKind::Ladder { 1 } else { 2 };
+                let (stairs_x, stairs_y) = (x + along, y + 2);
+                for &(tile_z, climb) in &[(z - 1, 1), (z, -1)] {
+                    stairs.push(Stairs {
+                        kind,
+                        tile: (stairs_x, stairs_y, tile_z),
+                        length,
+                        climb,
+                    });
+                    // steps and landing
+                    for i in -1..=length {
+                        taken.push((stairs_x, stairs_y + i, tile_z));
+                    }
+                }
+            }
+            floor_below = first_room..rooms.len();
+        }
+
+        for room in &mut rooms {
+            let (_, y, z) = room.cell;
+            room.outdoors = z == 0 && y == spec.grid.1 - 1;
+            room.torch = random.below(TORCH_COLORS);
+        }
+
+        let (x, y, z) = rooms[0].min();
+        let player = (x + player.0, y + player.1, z);
+        taken.push(player);
+        let mut monsters = vec![];
+        let mut familiars = vec![];
+        for (i, room) in rooms.iter().enumerate() {
+            if i != 0 && random.unilateral() < spec.monsters {
+                monsters.extend(free_tile(room, &mut taken, &mut random));
+            }
+            // the hero always gets one
+            if i == 0 || random.unilateral() < spec.familiars {
+                familiars.extend(free_tile(room, &mut taken, &mut random));
+            }
+        }
+
+        Self {
+            seed,
+            rooms,
+            stairs,
+            player,
+            monsters,
+            familiars,
+        }
+    }
+}
+
+/// How many torch colors `RoomPlan::torch` picks from
+pub const TORCH_COLORS: usize = 3;
+
+fn in_grid((x, y, _): Cell, (width, height): (i32, i32)) -> bool {
+    x >= 0 && y >= 0 && x < width && y < height
+}
+
+/// Between neighbors `from` and `to`, on `from`'s `side`, at the same tile for both
+fn add_door(rooms: &mut [RoomPlan], from: usize, to: usize, side: Side, random: &mut Random) {
+    let length = match side {
+        Side::West | Side::East => ROOM_HEIGHT,
+        Side::South | Side::North => ROOM_WIDTH,
+    };
+    // away from the corners
+    let along = 2 + random.below((length - 4) as usize) as i32;
+    rooms[from].doors[side as usize] = Some(along);
+    rooms[to].doors[side.opposite() as usize] = Some(along);
+}
+
+/// Somewhere inside `room` not next to its walls, and not `taken` yet
+fn free_tile(room: &RoomPlan, taken: &mut Vec<TilePos>, random: &mut Random) -> Option<TilePos> {
+    let (min_x, min_y, z) = room.min();
+    for _ in 0..20 {
+        let x = min_x + 2 + random.below((ROOM_WIDTH - 4) as usize) as i32;
+        let y = min_y + 2 + random.below((ROOM_HEIGHT - 4) as usize) as i32;
+        if !taken.contains(&(x, y, z)) {
+            taken.push((x, y, z));
+            return Some((x, y, z));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let spec = DungeonSpec::default();
+        assert_eq!(Dungeon::generate(7, &spec), Dungeon::generate(7, &spec));
+        let other = Dungeon::generate(8, &spec);
+        assert_ne!(Dungeon::generate(7, &spec).rooms, other.rooms);
+    }
+
+    #[test]
+    fn every_room_can_be_reached() {
+        let spec = DungeonSpec::default();
+        for seed in 0..50 {
+            let dungeon = Dungeon::generate(seed, &spec);
+            let rooms = &dungeon.rooms;
+            assert_eq!(rooms.len(), spec.rooms_per_floor * spec.floors as usize);
+            let index = |cell: Cell| rooms.iter().position(|r| r.cell == cell);
+
+            let mut reached = vec![false; rooms.len()];
+            let mut todo = vec![0];
+            while let Some(i) = todo.pop() {
+                if reached[i] {
+                    continue;
+                }
+                reached[i] = true;
+                let room = &rooms[i];
+                for &side in &Side::ALL {
+                    if let Some(along) = room.door(side) {
+                        let j = index(side.step(room.cell)).expect("door to nowhere");
+                        assert_eq!(rooms[j].door(side.opposite()), Some(along), "seed {}", seed);
+                        todo.push(j);
+                    }
+                }
+                for stairs in &dungeon.stairs {
+                    let (x, y, z) = stairs.tile;
+                    let cell = (x / ROOM_WIDTH, y / ROOM_HEIGHT, z);
+                    if cell == room.cell {
+                        todo.extend(index((cell.0, cell.1, z + stairs.climb)));
+                    }
+                }
+            }
+            assert!(reached.iter().all(|&r| r), "seed {}: {:?}", seed, reached);
+        }
+    }
+}
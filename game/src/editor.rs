@@ -0,0 +1,524 @@
+use super::*;
+use level::{Level, Torch};
+
+/// In meters per second, at zoom 1
+const CAMERA_SPEED: f32 = 12.0;
+const LABEL_SCALE: f32 = 2.0;
+
+/// In-game level editor, toggled with F2. The game stands still while the level is drawn as
+/// a plan of the camera's floor: arrows move the camera, Home/End change floors, Tab picks
+/// the brush, the left button paints it or selects what is on the tile, the right one erases,
+/// +/- change the selection and F5 saves. Leaving restarts the game on the level as edited
+#[derive(Default)]
+pub struct Editor {
+    /// Only while editing
+    level: Option<Level>,
+    brush: usize,
+    selected: Option<Thing>,
+    edited: bool,
+    /// Where the game's camera was and its zoom, put back when leaving
+    game_camera: Option<(WorldPosition, f32)>,
+    /// What the last action did, or why it didn't
+    status: String,
+}
+
+/// Something on a tile, by its index in the `Level`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Thing {
+    Spawn(usize),
+    Torch(usize),
+    Stairs(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Brush {
+    Wall,
+    Dirt,
+    Grass,
+    Player,
+    Monster,
+    Familiar,
+    Prop,
+    Torch,
+    Stairs,
+    Ladder,
+}
+
+const BRUSHES: [Brush; 10] = [
+    Brush::Wall,
+    Brush::Dirt,
+    Brush::Grass,
+    Brush::Player,
+    Brush::Monster,
+    Brush::Familiar,
+    Brush::Prop,
+    Brush::Torch,
+    Brush::Stairs,
+    Brush::Ladder,
+];
+
+impl Editor {
+    pub fn enabled(&self) -> bool {
+        self.level.is_some()
+    }
+
+    /// Starts editing `world`, or stops, returning the edited level to restart on if it changed.
+    /// Stopping puts `camera` back where the game left it, zoom included
+    pub fn toggle(&mut self, input: &Input, camera: &mut Camera, world: &World) -> Option<World> {
+        if !input.pressed(|i| i.edit) {
+            return None;
+        }
+        if let Some((p, zoom)) = self.game_camera.take() {
+            camera.p = p;
+            camera.zoom = zoom;
+        }
+        match self.level.take() {
+            None => {
+                self.level = Some(Level::from_world(world));
+                self.selected = None;
+                self.edited = false;
+                self.game_camera = Some((camera.p, camera.zoom));
+                self.status.clear();
+                None
+            }
+            Some(level) if self.edited => Some(level.build()),
+            Some(_) => None,
+        }
+    }
+
+    /// `world` only lends its geometry, the level being edited is apart from it
+    pub fn update(
+        &mut self, input: &Input, camera: &mut Camera, world: &World, host_api: &dyn HostApi,
+    ) {
+        let level = match &mut self.level {
+            Some(level) => level,
+            None => return,
+        };
+
+        let mut direction = V2::default();
+        if input.new.up {
+            direction += V2::new(0.0, 1.0);
+        }
+        if input.new.down {
+            direction += V2::new(0.0, -1.0);
+        }
+        if input.new.right {
+            direction += V2::new(1.0, 0.0);
+        }
+        if input.new.left {
+            direction += V2::new(-1.0, 0.0);
+        }
+        let speed = CAMERA_SPEED / camera.zoom * input.time_per_frame;
+        camera.p = world.map_into_chunk_space(camera.p, speed * direction);
+        if input.pressed(|i| i.floor_up) {
+            camera.p.abs.z += 1;
+        }
+        if input.pressed(|i| i.floor_down) {
+            camera.p.abs.z -= 1;
+        }
+
+        if input.pressed(|i| i.next_entity) {
+            self.brush = (self.brush + 1) % BRUSHES.len();
+        }
+
+        let tile = tile_under_mouse(input, camera, world);
+        let on_tile = thing_at(level, tile);
+        let brush = BRUSHES[self.brush];
+        if input.new.mouse_left {
+            let changed = match brush {
+                Brush::Wall | Brush::Dirt | Brush::Grass if on_tile.is_some() => {
+                    if input.pressed(|i| i.mouse_left) {
+                        self.status = "something stands there".to_owned();
+                    }
+                    false
+                }
+                Brush::Wall => {
+                    let ground = level.tiles.get(&tile).map_or(Ground::Dirt, |t| t.ground);
+                    let wall = MapTile { ground, wall: true };
+                    level.tiles.insert(tile, wall) != Some(wall)
+                }
+                Brush::Dirt | Brush::Grass => {
+                    let ground = if brush == Brush::Dirt {
+                        Ground::Dirt
+                    } else {
+                        Ground::Grass
+                    };
+                    let floor = MapTile {
+                        ground,
+                        wall: false,
+                    };
+                    level.tiles.insert(tile, floor) != Some(floor)
+                }
+                _ if !input.pressed(|i| i.mouse_left) => false,
+                _ if on_tile.is_some() => {
+                    self.selected = on_tile;
+                    false
+                }
+                _ => match place(level, brush, tile) {
+                    Ok(thing) => {
+                        self.selected = Some(thing);
+                        true
+                    }
+                    Err(e) => {
+                        self.status = e;
+                        false
+                    }
+                },
+            };
+            self.edited |= changed;
+        }
+        match on_tile {
+            Some(thing) if input.pressed(|i| i.mouse_right) => {
+                remove(level, thing);
+                self.selected = None;
+                self.edited = true;
+            }
+            None if input.new.mouse_right => {
+                self.edited |= level.tiles.remove(&tile).is_some();
+            }
+            _ => {}
+        }
+
+        let step = if input.pressed(|i| i.increase) {
+            1
+        } else if input.pressed(|i| i.decrease) {
+            -1
+        } else {
+            0
+        };
+        if let (Some(thing), true) = (self.selected, step != 0) {
+            match change(level, thing, step) {
+                Ok(()) => self.edited = true,
+                Err(e) => self.status = e,
+            }
+        }
+
+        if input.pressed(|i| i.save) {
+            self.status = save(level, host_api);
+        }
+    }
+
+    pub fn draw(
+        &self, buffer: &mut OffscreenBuffer, input: &Input, camera: &Camera, world: &World,
+    ) {
+        let level = match &self.level {
+            Some(level) => level,
+            None => return,
+        };
+        let screen = V2::new(buffer.width as f32, buffer.height as f32);
+        buffer.render_rectangle(V2::default(), screen, Color::black());
+
+        let z = camera.p.abs.z;
+        let tile_pixels = world.tile_side * camera.meters_to_pixels();
+        let (center_x, center_y, _) = world.tile_at(camera.p);
+        let span_x = (camera.screen_center().x() / tile_pixels).ceil() as i32 + 1;
+        let span_y = (camera.screen_center().y() / tile_pixels).ceil() as i32 + 1;
+        let to_screen = |(x, y, z): (i32, i32, i32)| {
+            let diff = world.substract(world.position_at_tile(x, y, z), camera.p);
+            camera.to_screen(diff.xy)
+        };
+        let half = V2::new(0.5 * tile_pixels, 0.5 * tile_pixels);
+        for y in center_y - span_y..=center_y + span_y {
+            for x in center_x - span_x..=center_x + span_x {
+                let tile = match level.tiles.get(&(x, y, z)) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let color = match (tile.ground, tile.wall) {
+                    (_, true) => gray(0.55),
+                    (Ground::Dirt, false) => rgb(0.4, 0.3, 0.2),
+                    (Ground::Grass, false) => rgb(0.25, 0.45, 0.2),
+                };
+                let center = to_screen((x, y, z));
+                // the gap draws the grid
+                let gap = V2::new(1.0, 1.0);
+                buffer.render_rectangle(center - half + gap, center + half, color);
+            }
+        }
+
+        for (i, spawn) in level.spawns.iter().enumerate() {
+            if spawn.tile().2 != z {
+                continue;
+            }
+            let (label, color) = match spawn {
+                Spawn::Player { .. } => ("P", Color::white()),
+                Spawn::Monster { .. } => ("M", Color::red()),
+                Spawn::Familiar { .. } => ("F", Color::yellow()),
+                Spawn::Prop { .. } => ("O", Color::green()),
+            };
+            let selected = self.selected == Some(Thing::Spawn(i));
+            label_tile(
+                buffer,
+                to_screen(spawn.tile()),
+                half,
+                label,
+                color,
+                selected,
+            );
+        }
+        for (i, torch) in level.torches.iter().enumerate() {
+            if torch.tile.2 != z {
+                continue;
+            }
+            let center = to_screen(torch.tile);
+            let radius = torch.radius * camera.meters_to_pixels();
+            buffer.render_circle(center, radius, torch.color.with_alpha(0.5));
+            let selected = self.selected == Some(Thing::Torch(i));
+            label_tile(buffer, center, half, "T", torch.color, selected);
+        }
+        for (i, stairs) in level.stairs.iter().enumerate() {
+            let (x, y, stairs_z) = stairs.tile;
+            // arrow up the flight, or down it from the floor it leads to
+            let (from, to) = if stairs_z == z {
+                ((x, y, z), (x, y + stairs.length - 1, z))
+            } else if stairs_z + stairs.climb == z {
+                ((x, y + stairs.length - 1, z), (x, y, z))
+            } else {
+                continue;
+            };
+            let (from, to) = (to_screen(from), to_screen(to));
+            let (top, bottom) = (from.y().min(to.y()), from.y().max(to.y()));
+            let min = V2::new(from.x(), top) - half;
+            let max = V2::new(from.x(), bottom) + half;
+            buffer.render_rectangle(min, max, rgb(0.45, 0.3, 0.15));
+            buffer.render_arrow(from, to, Color::white());
+            if self.selected == Some(Thing::Stairs(i)) {
+                buffer.render_outline(min, max, Color::yellow());
+            }
+        }
+
+        let hovered = to_screen(tile_under_mouse(input, camera, world));
+        buffer.render_outline(hovered - half, hovered + half, Color::white());
+
+        let mut text = format!(
+            "editing floor {}\nbrush: {:?} (tab)\nleft: paint, select\nright: erase\n+/-: change\nhome/end: floor\nf5: save, f2: play",
+            z,
+            BRUSHES[self.brush],
+        );
+        if let Some(thing) = self.selected {
+            text += &format!("\n\n{}", describe(level, thing));
+        }
+        if !self.status.is_empty() {
+            text += &format!("\n\n{}", self.status);
+        }
+        panel(buffer, V2::new(4.0, 4.0), &text);
+    }
+}
+
+fn rgb(red: f32, green: f32, blue: f32) -> Color {
+    Color {
+        red,
+        green,
+        blue,
+        alpha: 1.0,
+    }
+}
+
+fn gray(shade: f32) -> Color {
+    rgb(shade, shade, shade)
+}
+
+fn label_tile(
+    buffer: &mut OffscreenBuffer, center: V2, half: V2, label: &str, color: Color, selected: bool,
+) {
+    let size = render::text_size(label, LABEL_SCALE);
+    buffer.render_text(label, center - 0.5 * size, LABEL_SCALE, color);
+    if selected {
+        buffer.render_outline(center - half, center + half, Color::yellow());
+    }
+}
+
+fn tile_under_mouse(input: &Input, camera: &Camera, world: &World) -> (i32, i32, i32) {
+    let mouse = V2::new(input.new.mouse_x as f32, input.new.mouse_y as f32);
+    let d = (1.0 / camera.meters_to_pixels()) * (mouse - camera.screen_center());
+    world.tile_at(world.map_into_chunk_space(camera.p, V2::new(d.x(), -d.y())))
+}
+
+/// Tiles a flight covers, on both of the floors it joins
+fn stairs_tiles(stairs: &Stairs) -> Vec<(i32, i32, i32)> {
+    let (x, y, z) = stairs.tile;
+    (0..stairs.length)
+        .flat_map(|i| vec![(x, y + i, z), (x, y + i, z + stairs.climb)])
+        .collect()
+}
+
+fn thing_at(level: &Level, tile: (i32, i32, i32)) -> Option<Thing> {
+    let spawn = level.spawns.iter().position(|s| s.tile() == tile);
+    let torch = level.torches.iter().position(|t| t.tile == tile);
+    let stairs = level
+        .stairs
+        .iter()
+        .position(|s| stairs_tiles(s).contains(&tile));
+    spawn
+        .map(Thing::Spawn)
+        .or_else(|| torch.map(Thing::Torch))
+        .or_else(|| stairs.map(Thing::Stairs))
+}
+
+fn is_open(level: &Level, tile: (i32, i32, i32)) -> bool {
+    level.tiles.get(&tile).map_or(false, |t| !t.wall) && thing_at(level, tile).is_none()
+}
+
+/// What `brush` paints, on `tile`
+fn place(level: &mut Level, brush: Brush, tile: (i32, i32, i32)) -> Result<Thing, String> {
+    if !is_open(level, tile) {
+        return Err("needs an open floor".to_owned());
+    }
+    let result = match brush {
+        Brush::Player | Brush::Monster | Brush::Familiar | Brush::Prop => {
+            level.spawns.push(match brush {
+                Brush::Player => Spawn::Player {
+                    tile,
+                    hit_points: PLAYER_HIT_POINTS,
+                },
+                Brush::Monster => Spawn::Monster {
+                    tile,
+                    hit_points: MONSTER_HIT_POINTS,
+                },
+                Brush::Familiar => Spawn::Familiar { tile },
+                _ => Spawn::Prop {
+                    tile,
+                    prop: Prop::ALL[0],
+                },
+            });
+            Thing::Spawn(level.spawns.len() - 1)
+        }
+        Brush::Torch => {
+            level.torches.push(Torch {
+                tile,
+                height: TORCH_HEIGHT,
+                color: TORCHES[0],
+                radius: TORCH_RADIUS,
+            });
+            Thing::Torch(level.torches.len() - 1)
+        }
+        _ => {
+            let (kind, length) = if brush == Brush::Ladder {
+                (StairsKind::Ladder, 1)
+            } else {
+                (StairsKind::Stairs, STAIRS_LENGTH)
+            };
+            let stairs = Stairs {
+                kind,
+                tile,
+                length,
+                climb: 1,
+            };
+            let tiles = stairs_tiles(&stairs);
+            if !tiles.iter().all(|&t| is_open(level, t)) {
+                return Err("needs open floor here and above".to_owned());
+            }
+            level.stairs.push(stairs);
+            Thing::Stairs(level.stairs.len() - 1)
+        }
+    };
+    Ok(result)
+}
+
+fn remove(level: &mut Level, thing: Thing) {
+    match thing {
+        Thing::Spawn(i) => {
+            level.spawns.remove(i);
+        }
+        Thing::Torch(i) => {
+            level.torches.remove(i);
+        }
+        Thing::Stairs(i) => {
+            level.stairs.remove(i);
+        }
+    }
+}
+
+/// Hit points of spawns, kind of props, radius of torches and length of stairs, by `step`.
+/// Hit points and length stay within what levels allow
+fn change(level: &mut Level, thing: Thing, step: i32) -> Result<(), String> {
+    match thing {
+        Thing::Spawn(i) => match &mut level.spawns[i] {
+            Spawn::Player { hit_points, .. } | Spawn::Monster { hit_points, .. } => {
+                let changed = (*hit_points as i32 + step).max(1) as usize;
+                if changed > MAX_HIT_POINTS {
+                    return Err(format!("{} hit points at most", MAX_HIT_POINTS));
+                }
+                *hit_points = changed;
+            }
+            Spawn::Familiar { .. } => return Err("familiars have nothing to change".to_owned()),
+            Spawn::Prop { prop, .. } => {
+                let i = Prop::ALL.iter().position(|p| p == prop).unwrap_or(0) as i32;
+                let count = Prop::ALL.len() as i32;
+                *prop = Prop::ALL[(i + step).rem_euclid(count) as usize];
+            }
+        },
+        Thing::Torch(i) => {
+            let torch = &mut level.torches[i];
+            torch.radius = (torch.radius + step as f32).max(1.0);
+        }
+        Thing::Stairs(i) => {
+            let stairs = level.stairs[i];
+            if stairs.kind == StairsKind::Ladder {
+                return Err("ladders have nothing to change".to_owned());
+            }
+            if stairs.length + step > MAX_STAIRS_LENGTH {
+                return Err(format!("{} steps long at most", MAX_STAIRS_LENGTH));
+            }
+            if step > 0 {
+                let (x, y, z) = stairs.tile;
+                let next = y + stairs.length;
+                let open = [(x, next, z), (x, next, z + stairs.climb)]
+                    .iter()
+                    .all(|&t| is_open(level, t));
+                if !open {
+                    return Err("needs open floor further up".to_owned());
+                }
+            }
+            let stairs = &mut level.stairs[i];
+            stairs.length = (stairs.length + step).max(1);
+        }
+    }
+    Ok(())
+}
+
+fn describe(level: &Level, thing: Thing) -> String {
+    match thing {
+        Thing::Spawn(i) => match level.spawns[i] {
+            Spawn::Player { hit_points, .. } => format!("player\nhit points: {}", hit_points),
+            Spawn::Monster { hit_points, .. } => format!("monster\nhit points: {}", hit_points),
+            Spawn::Familiar { .. } => "familiar".to_owned(),
+            Spawn::Prop { prop, .. } => format!("prop\nkind: {}", prop.name()),
+        },
+        Thing::Torch(i) => format!("torch\nradius: {}", level.torches[i].radius),
+        Thing::Stairs(i) => {
+            let stairs = level.stairs[i];
+            match stairs.kind {
+                StairsKind::Stairs => format!("stairs\nlength: {}", stairs.length),
+                StairsKind::Ladder => "ladder".to_owned(),
+            }
+        }
+    }
+}
+
+/// Into the level file `GAME_LEVEL` names, so it is the one loaded on restart, unless that is
+/// a Tiled map: those are edited in Tiled. Nothing is written if the text wouldn't load back
+fn save(level: &Level, host_api: &dyn HostApi) -> String {
+    let path = std::env::var("GAME_LEVEL")
+        .ok()
+        .filter(|p| !p.ends_with(".json"))
+        .unwrap_or_else(|| "levels/edited.level".to_owned());
+    let text = format!("; saved from the editor\n\n{}", level.to_text());
+    if let Err(e) = level::load(&text) {
+        error!(
+            host_api,
+            "editor", "not saving {}, it wouldn't load: {}", path, e
+        );
+        return format!("not saved, it wouldn't load: {}", e);
+    }
+    match host_api.write_file(&path, text.as_bytes()) {
+        Ok(()) => {
+            info!(host_api, "editor", "saved {}", path);
+            format!("saved {}", path)
+        }
+        Err(e) => {
+            error!(host_api, "editor", "failed to save {}: {}", path, e);
+            format!("failed to save {}", path)
+        }
+    }
+}
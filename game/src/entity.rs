@@ -1,5 +1,5 @@
-use std::collections::BTreeMap;
 use super::*;
+use std::ops::Index;
 
 const HIT_POINT_SUB_COUNT: u8 = 4;
 
@@ -41,6 +41,12 @@ impl Default for MoveSpec {
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Default, Hash, Ord, PartialOrd)]
 pub struct StorageIdx(usize);
 
+impl StorageIdx {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct SimEntity {
     pub idx: StorageIdx,
@@ -57,7 +63,10 @@ pub struct SimEntity {
 
     pub z: f32,
     pub dz: f32,
+    /// Height of the ground under the entity above its floor, only ever not 0 on stairs
+    pub ground_z: f32,
 
+    /// Floor the entity is on
     pub chunk_z: i32,
     pub abs_tile_z: i32,
 
@@ -66,24 +75,84 @@ pub struct SimEntity {
 
     pub sword: Option<StorageIdx>,
     pub hit_points: Vec<HitPoint>,
+    /// Seconds left before it can be hurt again
+    pub hurt: f32,
     pub facing_direction: usize,
     pub distance_remaining: f32,
     pub t_bob: f32,
+    pub animation: AnimationState,
+    /// Floors gained by taking these stairs, negative going down
+    pub climb: i32,
+}
+
+/// The entities of a `SimRegion` sorted by index, on the transient arena.
+/// Holds at most the entities `SimRegion::begin` loaded, updates replace them in place
+pub struct SimEntities<'a> {
+    entities: ArenaStack<'a, SimEntity>,
+}
+
+impl<'a> SimEntities<'a> {
+    fn new(arena: &'a Arena, capacity: usize) -> Self {
+        Self {
+            entities: arena.stack(capacity),
+        }
+    }
+
+    fn position(&self, idx: StorageIdx) -> Result<usize, usize> {
+        self.entities.binary_search_by_key(&idx, |e| e.idx)
+    }
+
+    fn insert(&mut self, entity: SimEntity) {
+        match self.position(entity.idx) {
+            Ok(i) => self.entities[i] = entity,
+            Err(i) => {
+                self.entities.push(entity);
+                self.entities[i..].rotate_right(1);
+            }
+        }
+    }
+
+    pub fn get(&self, idx: &StorageIdx) -> Option<&SimEntity> {
+        self.position(*idx).ok().map(|i| &self.entities[i])
+    }
+
+    pub fn contains_key(&self, idx: &StorageIdx) -> bool {
+        self.position(*idx).is_ok()
+    }
+
+    /// In index order
+    pub fn values(&self) -> std::slice::Iter<'_, SimEntity> {
+        self.entities.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+}
+
+impl<'a> Index<&StorageIdx> for SimEntities<'a> {
+    type Output = SimEntity;
+
+    fn index(&self, idx: &StorageIdx) -> &SimEntity {
+        self.get(idx).expect("entity not in the sim region")
+    }
 }
 
 pub struct SimRegion<'a> {
     storage: &'a mut Storage,
     world: &'a mut World,
+    arena: &'a Arena,
     pub origin: WorldPosition,
     bounds: Rect2,
     updatable_bounds: Rect2,
 
-    pub entities: BTreeMap<StorageIdx, SimEntity>,
+    pub entities: SimEntities<'a>,
 }
 
 impl<'a> SimRegion<'a> {
+    /// The region's entities live in `arena` until it is reset
     pub fn new(
-        storage: &'a mut Storage, world: &'a mut World, origin: WorldPosition,
+        storage: &'a mut Storage, world: &'a mut World, arena: &'a Arena, origin: WorldPosition,
         updatable_bounds: Rect2,
     ) -> Self {
         let update_range = 1.0;
@@ -93,13 +162,15 @@ impl<'a> SimRegion<'a> {
         SimRegion {
             storage,
             world,
+            arena,
             origin,
             bounds,
             updatable_bounds,
-            entities: BTreeMap::new(),
+            entities: SimEntities::new(arena, 0),
         }
     }
 
+    /// Loads the entities of the origin's floor and of the floors right above and below it
     pub fn begin(&mut self) {
         let min_chunk = self
             .world
@@ -108,37 +179,53 @@ impl<'a> SimRegion<'a> {
             .world
             .map_into_chunk_space(self.origin, self.bounds.max());
 
-        let mut new_entities = vec![];
-        for chunk_y in min_chunk.abs.y..=max_chunk.abs.y {
-            for chunk_x in min_chunk.abs.x..=max_chunk.abs.x {
-                let chunk_idx = ChunkIdx::new(chunk_x, chunk_y, self.origin.abs.z);
-                if let Some(chunk) = self.world.chunk(chunk_idx) {
-                    let indices = chunk.entities().iter().map(|idx| StorageIdx(*idx));
-                    for idx in indices {
-                        let low_entity = self.storage.get(idx);
-                        if low_entity.entity.spatial {
-                            let p = self.get_sim_space_p(&low_entity);
-                            if self.bounds.contains(p) {
-                                let entity = self.load_entity(idx);
-                                if let Some(sword) = entity.sword {
-                                    let entity = self.load_entity(sword);
+        let floor = self.origin.abs.z;
+        let mut candidates = 0;
+        for chunk_z in floor - 1..=floor + 1 {
+            for chunk_y in min_chunk.abs.y..=max_chunk.abs.y {
+                for chunk_x in min_chunk.abs.x..=max_chunk.abs.x {
+                    let chunk_idx = ChunkIdx::new(chunk_x, chunk_y, chunk_z);
+                    if let Some(chunk) = self.world.chunk(chunk_idx) {
+                        candidates += chunk.entities().len();
+                    }
+                }
+            }
+        }
+        // every candidate might bring its sword along
+        let mut new_entities = self.arena.stack(2 * candidates);
+        for chunk_z in floor - 1..=floor + 1 {
+            for chunk_y in min_chunk.abs.y..=max_chunk.abs.y {
+                for chunk_x in min_chunk.abs.x..=max_chunk.abs.x {
+                    let chunk_idx = ChunkIdx::new(chunk_x, chunk_y, chunk_z);
+                    if let Some(chunk) = self.world.chunk(chunk_idx) {
+                        let indices = chunk.entities().iter().map(|idx| StorageIdx(*idx));
+                        for idx in indices {
+                            let low_entity = self.storage.get(idx);
+                            if low_entity.entity.spatial {
+                                let p = self.get_sim_space_p(&low_entity);
+                                if self.bounds.contains(p) {
+                                    let entity = self.load_entity(idx);
+                                    if let Some(sword) = entity.sword {
+                                        let entity = self.load_entity(sword);
+                                        new_entities.push(entity);
+                                    }
+                                    //TODO improve this - `new_entities` makes the borrow_checker happy
+                                    // after it, we could call `load_entity` instead
                                     new_entities.push(entity);
                                 }
-                                //TODO improve this - `new_entities` makes the borrow_checker happy
-                                // after it, we could call `load_entity` instead
-                                new_entities.push(entity);
                             }
                         }
                     }
                 }
             }
         }
+        self.entities = SimEntities::new(self.arena, new_entities.len());
         self.update_entities(new_entities);
     }
 
-    pub fn update_entities(&mut self, new_entities: Vec<SimEntity>) {
+    pub fn update_entities(&mut self, new_entities: impl IntoIterator<Item = SimEntity>) {
         for entity in new_entities {
-            self.entities.insert(entity.idx, entity);
+            self.entities.insert(entity);
         }
     }
 
@@ -163,22 +250,29 @@ impl<'a> SimRegion<'a> {
         let mut player_delta: V2 = 0.5 * ddp * dt.powi(2) + entity.dp * dt;
         entity.dp = ddp * dt + entity.dp;
 
+        if entity.collides {
+            debug_draw::arrow(entity.p, entity.p + 0.25 * entity.dp, Color::green());
+        }
+
         // collision detection
         {
-            for _ in 0..4 {
+            for iteration in 0..4 {
                 let mut t_min = 1.0;
                 let desired_position: V2 = entity.p + player_delta;
                 let mut wall_normal = V2::default();
                 let mut collided_idx = None;
                 let can_collide = entity.collides && entity.spatial;
                 if can_collide {
-                    for (idx, test_entity) in &self.entities {
+                    for test_entity in self.entities.values() {
                         if test_entity.idx == entity.idx {
                             continue;
                         }
                         if !test_entity.collides || !test_entity.spatial {
                             continue;
                         }
+                        if test_entity.chunk_z != entity.chunk_z {
+                            continue;
+                        }
 
                         //minkowski
                         let diameter_w = test_entity.width + entity.width;
@@ -187,14 +281,18 @@ impl<'a> SimRegion<'a> {
                         let corner_y = V2::new(-0.5 * diameter_h, 0.5 * diameter_h);
 
                         let rel: V2 = entity.p - test_entity.p;
+                        if iteration == 0 && rel.len() < diameter_w + diameter_h {
+                            let dim = V2::new(diameter_w, diameter_h);
+                            debug_draw::rect(test_entity.p, dim, Color::magenta());
+                        }
 
                         if test_wall(&mut t_min, corner_x.min(), rel, player_delta, corner_y) {
                             wall_normal = V2::new(-1.0, 0.0);
-                            collided_idx = Some(idx);
+                            collided_idx = Some(test_entity.idx);
                         }
                         if test_wall(&mut t_min, corner_x.max(), rel, player_delta, corner_y) {
                             wall_normal = V2::new(1.0, 0.0);
-                            collided_idx = Some(idx);
+                            collided_idx = Some(test_entity.idx);
                         }
 
                         if test_wall(
@@ -205,7 +303,7 @@ impl<'a> SimRegion<'a> {
                             corner_x,
                         ) {
                             wall_normal = V2::new(0.0, -1.0);
-                            collided_idx = Some(idx);
+                            collided_idx = Some(test_entity.idx);
                         }
 
                         if test_wall(
@@ -216,7 +314,7 @@ impl<'a> SimRegion<'a> {
                             corner_x,
                         ) {
                             wall_normal = V2::new(0.0, 1.0);
-                            collided_idx = Some(idx);
+                            collided_idx = Some(test_entity.idx);
                         }
                     }
                 }
@@ -227,6 +325,7 @@ impl<'a> SimRegion<'a> {
                 //move entity
                 entity.p += t_min * player_delta;
                 if let Some(_collided_idx) = collided_idx.take() {
+                    debug_draw::arrow(entity.p, entity.p + 0.5 * wall_normal, Color::red());
                     entity.dp = entity.dp - entity.dp.inner(wall_normal) * wall_normal;
                     player_delta = desired_position - entity.p;
                     player_delta = player_delta - player_delta.inner(wall_normal) * wall_normal;
@@ -237,6 +336,10 @@ impl<'a> SimRegion<'a> {
             }
         }
 
+        if entity.collides {
+            self.climb(old.p, &mut entity);
+        }
+
         //facing direction
         {
             if entity.dp.x() == 0.0 && entity.dp.y() == 0.0 {
@@ -263,14 +366,77 @@ impl<'a> SimRegion<'a> {
         entity
     }
 
+    /// Raises `entity`, which just moved from `from`, along the stairs it walks on and takes it
+    /// to another floor when it leaves a flight by its far end or steps onto a ladder. Stairs
+    /// are only walked onto from their near end: from their sides they block like walls
+    fn climb(&self, from: V2, entity: &mut SimEntity) {
+        entity.ground_z = 0.0;
+        let floor_height = self.world.floor_height;
+        for stairs in self.entities.values() {
+            if stairs.chunk_z != entity.chunk_z || !stairs.spatial {
+                continue;
+            }
+            let bounds = Rect2::new_center_dim(stairs.p, V2::new(stairs.width, stairs.height));
+            let was_on = bounds.contains(from);
+            let is_on = bounds.contains(entity.p);
+            match stairs.kind {
+                EntityKind::Ladder if is_on && !was_on => {
+                    entity.chunk_z += stairs.climb;
+                    return;
+                }
+                EntityKind::Stairs => {
+                    // going up, the near end is at the bottom of the screen
+                    let up = stairs.climb > 0;
+                    if is_on {
+                        let from_near_end = if up {
+                            from.y() < bounds.min().y()
+                        } else {
+                            from.y() >= bounds.max().y()
+                        };
+                        if !was_on && !from_near_end {
+                            entity.p = from;
+                            entity.dp = V2::default();
+                            return;
+                        }
+                        let t = (entity.p.y() - bounds.min().y()) / stairs.height;
+                        let t = if up { t } else { t - 1.0 };
+                        entity.ground_z = t * floor_height * stairs.climb.abs() as f32;
+                        return;
+                    }
+                    let off_far_end = if up {
+                        entity.p.y() >= bounds.max().y()
+                    } else {
+                        entity.p.y() < bounds.min().y()
+                    };
+                    if was_on && off_far_end {
+                        entity.chunk_z += stairs.climb;
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Rect2 {
+        self.bounds
+    }
+
+    pub fn updatable_bounds(&self) -> Rect2 {
+        self.updatable_bounds
+    }
+
     pub fn end(self, entity_focused_by_camera: Option<StorageIdx>) -> Option<WorldPosition> {
         let mut new_camera = None;
         let len = self.storage.len();
-        for (idx, mut entity) in self.entities.into_iter() {
+        for mut entity in self.entities.entities.into_iter() {
+            let idx = entity.idx;
             assert!(entity.simming);
             entity.simming = false;
             let new_p = if entity.spatial {
-                self.world.map_into_chunk_space(self.origin, entity.p)
+                let mut p = self.world.map_into_chunk_space(self.origin, entity.p);
+                p.abs.z = entity.chunk_z;
+                p
             } else {
                 WorldPosition::default()
             };
@@ -302,6 +468,9 @@ impl<'a> SimRegion<'a> {
         assert!(!entity.simming);
         entity.idx = idx;
         entity.simming = true;
+        if entity.spatial {
+            entity.chunk_z = low_entity.p.abs.z;
+        }
         entity.p = p;
         entity.updatable = self.updatable_bounds.contains(p);
         assert!(!self.entities.contains_key(&idx));
@@ -355,6 +524,19 @@ pub struct HitPoint {
     filled: u8,
 }
 
+impl SimEntity {
+    /// Empties the last hit point that isn't empty yet. `false` if they all are
+    pub fn take_hit(&mut self) -> bool {
+        match self.hit_points.iter_mut().rev().find(|h| h.filled > 0) {
+            Some(hit_point) => {
+                hit_point.filled = 0;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl HitPoint {
     pub fn full() -> Self {
         Self {
@@ -362,6 +544,10 @@ impl HitPoint {
             filled: HIT_POINT_SUB_COUNT,
         }
     }
+
+    pub fn filled(&self) -> u8 {
+        self.filled
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -371,6 +557,9 @@ pub enum EntityKind {
     Familiar,
     Monster,
     Sword,
+    Stairs,
+    Ladder,
+    Prop(Prop),
 }
 
 impl Default for EntityKind {
@@ -413,3 +602,121 @@ impl Storage {
         self.entities.get_mut(idx.0).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(
+        world: &mut World, storage: &mut Storage, tile: (i32, i32), entity: SimEntity,
+    ) -> StorageIdx {
+        let p = world.position_at_tile(tile.0, tile.1, 0);
+        let mut low = LowEntity {
+            entity,
+            p: WorldPosition::default(),
+        };
+        world.change_entity_chunks(storage.len(), &mut low, p);
+        storage.push(low)
+    }
+
+    /// One tile of stairs at tile (2, 2), and a hero right below it
+    fn stairwell() -> (World, Storage, StorageIdx) {
+        let mut world = World::new();
+        let mut storage = Storage::default();
+        let side = world.tile_side;
+        let stairs = SimEntity {
+            kind: EntityKind::Stairs,
+            width: side,
+            height: side,
+            spatial: true,
+            climb: 1,
+            ..Default::default()
+        };
+        add(&mut world, &mut storage, (2, 2), stairs);
+        let hero = SimEntity {
+            kind: EntityKind::Player,
+            width: 0.5,
+            height: 0.5,
+            spatial: true,
+            collides: true,
+            ..Default::default()
+        };
+        let hero = add(&mut world, &mut storage, (2, 1), hero);
+        (world, storage, hero)
+    }
+
+    /// Moves `hero` at `dp` for `steps` tenths of a second, `check`ing it after every step
+    fn walk(
+        region: &mut SimRegion, hero: StorageIdx, dp: V2, steps: usize,
+        mut check: impl FnMut(&SimEntity),
+    ) {
+        for _ in 0..steps {
+            let mut entity = region.entities[&hero].clone();
+            entity.dp = dp;
+            let entity = region.move_entity(&entity, 0.1, V2::default(), MoveSpec::default());
+            check(&entity);
+            region.update_entities(Some(entity));
+        }
+    }
+
+    #[test]
+    fn stairs_lift_entities_to_the_next_floor() {
+        let (mut world, mut storage, hero) = stairwell();
+        let side = world.tile_side;
+        let origin = world.position_at_tile(2, 2, 0);
+        let bounds = Rect2::new_center_dim(V2::default(), V2::new(20.0, 20.0));
+        let mut memory = vec![0u8; 1 << 16];
+        let arena = unsafe { Arena::new(&mut memory) };
+        let mut region = SimRegion::new(&mut storage, &mut world, &arena, origin, bounds);
+        region.begin();
+
+        let mut ground_z = 0.0;
+        walk(&mut region, hero, V2::new(0.0, side), 20, |entity| {
+            if entity.chunk_z == 0 {
+                assert!(
+                    entity.ground_z >= ground_z,
+                    "{} < {}",
+                    entity.ground_z,
+                    ground_z
+                );
+                ground_z = entity.ground_z;
+            } else {
+                assert!(
+                    entity.ground_z == 0.0,
+                    "{} above the floor",
+                    entity.ground_z
+                );
+            }
+        });
+        assert!(ground_z > 2.0, "only got {} up", ground_z);
+        assert_eq!(region.entities[&hero].chunk_z, 1);
+
+        region.end(None);
+        assert_eq!(storage.get(hero).p.abs.z, 1);
+    }
+
+    #[test]
+    fn stairs_block_from_the_side() {
+        let (mut world, mut storage, hero) = stairwell();
+        let side = world.tile_side;
+        let origin = world.position_at_tile(1, 2, 0);
+        let bounds = Rect2::new_center_dim(V2::default(), V2::new(20.0, 20.0));
+        let mut memory = vec![0u8; 1 << 16];
+        let arena = unsafe { Arena::new(&mut memory) };
+        let mut region = SimRegion::new(&mut storage, &mut world, &arena, origin, bounds);
+        region.begin();
+        // moves the hero next to the stairs first
+        let mut entity = region.entities[&hero].clone();
+        entity.p = V2::new(0.0, 0.0);
+        region.update_entities(Some(entity));
+
+        walk(&mut region, hero, V2::new(side, 0.0), 10, |entity| {
+            assert!(
+                entity.p.x() < 0.5 * side,
+                "walked onto the stairs: {:?}",
+                entity.p
+            );
+            assert_eq!((entity.chunk_z, entity.ground_z), (0, 0.0));
+        });
+    }
+}
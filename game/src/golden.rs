@@ -0,0 +1,431 @@
+//! Renders fixed scenes and compares them against the reference images in `game/golden`.
+//! A failing scene leaves `<name>.actual.bmp` and `<name>.diff.bmp` next to its reference,
+//! with every pixel off by more than `TOLERANCE` in red.
+//! `GOLDEN_UPDATE=1 cargo test -p game golden` rewrites the references.
+//! `Headless` only stands in for the host while drawing: whole `game_update` frames load
+//! assets, which are not part of the repo
+
+use super::*;
+use render::{ClipRect, Lighting, Palette, RenderTarget};
+use std::path::PathBuf;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+/// Per channel: leaves room for float differences across CPUs, not for a shifted sprite
+const TOLERANCE: u8 = 2;
+
+/// Answers `RenderGroup::draw` and nothing else
+struct Headless;
+
+impl HostApi for Headless {
+    /// Backwards, so that tiles depending on the order they are drawn in show up
+    fn parallel_for(&self, count: usize, job: &(dyn Fn(usize) + Sync)) {
+        for i in (0..count).rev() {
+            job(i);
+        }
+    }
+
+    fn update_canvas(&mut self, _buffer: &[u8], _pitch: usize) {
+    }
+
+    fn generate_audio(&mut self) {
+    }
+
+    fn load_bmp(&self, _path: &str) -> Bitmap {
+        Bitmap {
+            align_x: 0,
+            align_y: 0,
+            width: 0,
+            height: 0,
+            pixels: vec![],
+        }
+    }
+
+    fn read_file(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        Err(no_files(path))
+    }
+
+    fn write_file(&self, path: &str, _contents: &[u8]) -> std::io::Result<()> {
+        Err(no_files(path))
+    }
+
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>> {
+        Err(no_files(path))
+    }
+
+    fn save_bmp(
+        &self, path: &str, _buffer: &[u8], _width: usize, _height: usize, _pitch: usize,
+    ) -> std::io::Result<()> {
+        Err(no_files(path))
+    }
+}
+
+fn no_files(path: &str) -> std::io::Error {
+    let msg = format!("{}: no files while rendering headless", path);
+    std::io::Error::new(std::io::ErrorKind::Other, msg)
+}
+
+fn buffer() -> OffscreenBuffer {
+    buffer_sized(WIDTH, HEIGHT)
+}
+
+fn buffer_sized(width: usize, height: usize) -> OffscreenBuffer {
+    let mut result = OffscreenBuffer {
+        buffer: vec![0; width * height * 4],
+        width,
+        height,
+        bytes_per_pixel: 4,
+    };
+    result.reset();
+    result
+}
+
+/// Little figure with soft edges: a translucent body under a round head
+fn sprite() -> Bitmap {
+    let (width, height) = (12, 16);
+    let mut result = Bitmap {
+        align_x: 0,
+        align_y: 0,
+        width,
+        height,
+        pixels: vec![0; width * height * 4],
+    };
+    let head = soft_dot(8);
+    {
+        let mut target = RenderTarget::from_bitmap(&mut result);
+        let body = Color {
+            red: 0.2,
+            green: 0.4,
+            blue: 0.9,
+            alpha: 0.8,
+        };
+        target.render_rectangle(V2::new(2.0, 7.0), V2::new(10.0, 16.0), body);
+        target.render_rectangle(V2::new(4.0, 9.0), V2::new(8.0, 11.0), Color::yellow());
+        target.render_bitmap(&head, V2::new(2.0, 0.0), Color::white(), None);
+    }
+    result
+}
+
+fn path(name: &str, suffix: &str) -> PathBuf {
+    let mut result = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    result.push("golden");
+    result.push(format!("{}{}.bmp", name, suffix));
+    result
+}
+
+/// 32 bits per pixel, bottom row first
+fn write_bmp(path: &PathBuf, pixels: &[u8], width: usize, height: usize) {
+    let size = pixels.len() as u32;
+    let mut bytes = Vec::with_capacity(54 + pixels.len());
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&(54 + size).to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&54u32.to_le_bytes());
+    bytes.extend_from_slice(&40u32.to_le_bytes());
+    bytes.extend_from_slice(&(width as i32).to_le_bytes());
+    bytes.extend_from_slice(&(height as i32).to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.extend_from_slice(&[0; 16]);
+    for row in pixels.chunks(width * 4).rev() {
+        bytes.extend_from_slice(row);
+    }
+    std::fs::write(path, bytes).unwrap();
+}
+
+/// Only reads what `write_bmp` writes. Top row first, like `OffscreenBuffer`
+fn read_bmp(path: &PathBuf) -> Option<(Vec<u8>, usize, usize)> {
+    let bytes = std::fs::read(path).ok()?;
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let (offset, width, height) = (
+        u32_at(10) as usize,
+        u32_at(18) as usize,
+        u32_at(22) as usize,
+    );
+    let pixels = bytes[offset..offset + width * height * 4]
+        .chunks(width * 4)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    Some((pixels, width, height))
+}
+
+fn check(name: &str, actual: &OffscreenBuffer) {
+    let pixels = &actual.buffer[..actual.height * actual.pitch()];
+    let reference = path(name, "");
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        write_bmp(&reference, pixels, actual.width, actual.height);
+        return;
+    }
+    let (expected, width, height) = read_bmp(&reference).unwrap_or_else(|| {
+        panic!("no {:?}, run with GOLDEN_UPDATE=1 to create it", reference);
+    });
+    assert_eq!(
+        (width, height),
+        (actual.width, actual.height),
+        "{}: size",
+        name
+    );
+
+    let mut diff = Vec::with_capacity(pixels.len());
+    let mut wrong = 0;
+    let mut first = None;
+    for (i, (a, e)) in pixels.chunks(4).zip(expected.chunks(4)).enumerate() {
+        let error = (0..4)
+            .map(|c| (a[c] as i32 - e[c] as i32).abs())
+            .max()
+            .unwrap() as u8;
+        if error > TOLERANCE {
+            wrong += 1;
+            first = first.or(Some((i % width, i / width)));
+            diff.extend_from_slice(&[0, 0, 128 + error / 2, 255]);
+        } else {
+            // faded, for context
+            let grey = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+            diff.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    if wrong > 0 {
+        write_bmp(&path(name, ".actual"), pixels, width, height);
+        write_bmp(&path(name, ".diff"), &diff, width, height);
+        panic!(
+            "{}: {} pixels off by more than {}, first at {:?}, see {:?}",
+            name,
+            wrong,
+            TOLERANCE,
+            first.unwrap(),
+            path(name, ".diff")
+        );
+    }
+}
+
+#[test]
+fn rectangles() {
+    let mut buffer = buffer();
+    buffer.render_rectangle(V2::new(-4.0, -4.0), V2::new(20.0, 12.0), Color::green());
+    buffer.render_rectangle(V2::new(10.4, 6.6), V2::new(40.5, 30.2), Color::red());
+    let glass = Color::white().with_alpha(0.3);
+    buffer.render_rectangle(V2::new(30.0, 20.0), V2::new(70.0, 40.0), glass);
+    let shade = Color::black().with_alpha(0.6);
+    buffer.render_rectangle(V2::new(5.0, 25.0), V2::new(35.0, 45.0), shade);
+    check("rectangles", &buffer);
+}
+
+#[test]
+fn bitmaps() {
+    let sprite = sprite();
+    let mut buffer = buffer();
+    buffer.render_rectangle(V2::new(0.0, 24.0), V2::new(64.0, 48.0), Color::white());
+    buffer.render_bitmap(&sprite, V2::new(2.0, 4.0), Color::white(), None);
+    buffer.render_bitmap(
+        &sprite,
+        V2::new(17.4, 4.6),
+        Color::white().with_alpha(0.5),
+        None,
+    );
+    let tint = Color {
+        red: 1.0,
+        green: 0.4,
+        blue: 0.4,
+        alpha: 1.0,
+    };
+    buffer.render_bitmap(&sprite, V2::new(30.0, 26.0), tint, None);
+    let palette = Palette::default().swap(Color::yellow(), Color::magenta());
+    buffer.render_bitmap(&sprite, V2::new(46.0, 26.0), Color::white(), Some(&palette));
+    check("bitmaps", &buffer);
+}
+
+#[test]
+fn quads() {
+    let sprite = sprite();
+    let mut buffer = buffer();
+    let size = V2::new(sprite.width as f32, sprite.height as f32);
+    let angle = 0.5f32;
+    let x_axis = size.x() * V2::new(angle.cos(), angle.sin());
+    let y_axis = (size.y() / size.x()) * x_axis.perp();
+    let origin = V2::new(14.0, 4.0);
+    let quad = Quad {
+        origin,
+        x_axis,
+        y_axis,
+    };
+    buffer.render_quad(&sprite, quad, Color::white(), None);
+    let quad = Quad {
+        origin: V2::new(36.3, 14.7),
+        x_axis: V2::new(1.5 * size.x(), 0.0),
+        y_axis: V2::new(0.0, 1.5 * size.y()),
+    };
+    buffer.render_quad(&sprite, quad, Color::white(), None);
+    check("quads", &buffer);
+}
+
+#[test]
+fn lighting() {
+    let sprite = sprite();
+    let normals = dome_normals(sprite.width, sprite.height, 0.8);
+    let lights = [
+        Light {
+            p: V2::new(8.0, 8.0),
+            height: 10.0,
+            color: Color::red(),
+            radius: 40.0,
+        },
+        Light {
+            p: V2::new(56.0, 40.0),
+            height: 6.0,
+            color: Color::white(),
+            radius: 30.0,
+        },
+    ];
+    let lighting = Lighting {
+        ambient: Color::black().with_alpha(1.0),
+        lights: &lights,
+    };
+    let mut buffer = buffer();
+    let mut target = buffer.target();
+    for (i, p) in [V2::new(6.0, 6.0), V2::new(26.0, 16.0), V2::new(46.0, 28.0)]
+        .iter()
+        .enumerate()
+    {
+        let palette = None;
+        if i == 1 {
+            let color = lighting.shade(Color::white(), *p + V2::new(6.0, 8.0));
+            target.render_bitmap(&sprite, *p, color, palette);
+        } else {
+            target.render_bitmap_lit(&sprite, &normals, *p, Color::white(), palette, &lighting);
+        }
+    }
+    check("lighting", &buffer);
+}
+
+#[test]
+fn shapes_and_text() {
+    let mut buffer = buffer();
+    buffer.render_line(V2::new(-10.0, 2.5), V2::new(70.0, 30.5), Color::white());
+    buffer.render_circle(V2::new(20.0, 20.0), 12.0, Color::green());
+    buffer.render_arrow(V2::new(40.0, 40.0), V2::new(58.0, 8.0), Color::yellow());
+    buffer.render_outline(V2::new(2.0, 30.0), V2::new(30.0, 46.0), Color::red());
+    let mut target = buffer.target();
+    target.push_clip(ClipRect::new(4, 32, 22, 44));
+    target.render_text("CLIPPED\nTEXT", V2::new(4.0, 33.0), 1.0, Color::white());
+    target.pop_clip();
+    check("shapes_and_text", &buffer);
+}
+
+/// Sorting, tiling and lighting together
+#[test]
+fn render_group() {
+    let sprite = sprite();
+    let mut memory = vec![0u8; 1 << 16];
+    let arena = unsafe { Arena::new(&mut memory) };
+    let mut group = RenderGroup::new(&arena, 16);
+    group.clear(Color::black());
+    group.set_ambient(Color::white().with_alpha(1.0));
+    group.push_light(Light {
+        p: V2::new(32.0, 24.0),
+        height: 8.0,
+        color: Color::yellow(),
+        radius: 24.0,
+    });
+    // pushed front to back, drawn back to front
+    group.push_bitmap_with(
+        SortKey::new(0.0, 0.0),
+        &sprite,
+        None,
+        V2::new(26.0, 20.0),
+        Color::white(),
+        None,
+    );
+    group.push_rectangle(
+        SortKey::new(1.0, 0.0),
+        V2::new(20.0, 14.0),
+        V2::new(44.0, 30.0),
+        Color::green(),
+    );
+    let x_axis = V2::new(10.0, 6.0);
+    let quad = Quad {
+        origin: V2::new(40.0, 8.0),
+        x_axis,
+        y_axis: (16.0 / 12.0) * x_axis.perp(),
+    };
+    group.push_quad(
+        SortKey::new(0.0, 1.0),
+        &sprite,
+        None,
+        quad,
+        Color::white(),
+        None,
+    );
+    let mut buffer = buffer();
+    group.draw(&mut buffer, &Headless);
+    check("render_group", &buffer);
+}
+
+/// Tiled and single threaded drawing give the same bytes, with tile sides that aren't whole
+/// pixels and sprites across tile edges
+#[test]
+fn render_group_tiles_match_single_threaded() {
+    let sprite = sprite();
+    let normals = dome_normals(sprite.width, sprite.height, 0.8);
+    let (width, height) = (61, 37);
+    let draw = |buffer: &mut OffscreenBuffer, tiled: bool| {
+        let mut memory = vec![0u8; 1 << 16];
+        let arena = unsafe { Arena::new(&mut memory) };
+        let mut group = RenderGroup::new(&arena, 16);
+        group.clear(Color::black());
+        group.set_ambient(Color::white().with_alpha(0.3));
+        group.push_light(Light {
+            p: V2::new(30.0, 18.0),
+            height: 8.0,
+            color: Color::yellow(),
+            radius: 24.0,
+        });
+        group.push_rectangle(
+            SortKey::background(),
+            V2::new(13.5, 7.25),
+            V2::new(47.5, 29.75),
+            Color::green().with_alpha(0.5),
+        );
+        for (i, &p) in [(9.5, 3.0), (24.25, 13.0), (39.0, 20.5)].iter().enumerate() {
+            let key = SortKey::new(i as f32, 0.0);
+            let p = V2::new(p.0, p.1);
+            group.push_bitmap_with(key, &sprite, Some(&normals), p, Color::white(), None);
+        }
+        group.push_bitmap_with(
+            SortKey::new(-1.0, 0.0),
+            &sprite,
+            None,
+            V2::new(52.0, 27.0),
+            Color::red(),
+            None,
+        );
+        let x_axis = V2::new(11.0, 5.0);
+        let quad = Quad {
+            origin: V2::new(12.0, 20.0),
+            x_axis,
+            y_axis: 1.3 * x_axis.perp(),
+        };
+        group.push_quad(
+            SortKey::new(0.5, 1.0),
+            &sprite,
+            Some(&normals),
+            quad,
+            Color::white(),
+            None,
+        );
+        if tiled {
+            group.draw(buffer, &Headless);
+        } else {
+            group.draw_single_threaded(buffer);
+        }
+    };
+    let mut tiled = buffer_sized(width, height);
+    draw(&mut tiled, true);
+    let mut single = buffer_sized(width, height);
+    draw(&mut single, false);
+    assert!(tiled.buffer == single.buffer);
+}
@@ -0,0 +1,255 @@
+use super::*;
+use render::RenderTarget;
+use std::collections::HashMap;
+
+const GRASS_BASE: Color = Color {
+    red: 0.28,
+    green: 0.42,
+    blue: 0.16,
+    alpha: 1.0,
+};
+const DIRT_BASE: Color = Color {
+    red: 0.36,
+    green: 0.29,
+    blue: 0.2,
+    alpha: 1.0,
+};
+
+/// Cached chunks kept once out of view, the ones seen least recently go first
+const MAX_CACHED_CHUNKS: usize = 32;
+/// Chunks with floor composited in a frame at most, the others show up over the next frames
+const MAX_BUILDS_PER_FRAME: usize = 2;
+
+/// Stamps scattered over the floor tiles
+pub struct GroundArt {
+    grass: Vec<Bitmap>,
+    dirt: Vec<Bitmap>,
+    tufts: Vec<Bitmap>,
+}
+
+impl GroundArt {
+    pub fn load(host_api: &dyn HostApi) -> Self {
+        let load = |name: &str, count: usize| {
+            (0..count)
+                .map(|i| load_bmp(host_api, &format!("assets/test2/{}{:02}.bmp", name, i)))
+                .collect()
+        };
+        Self {
+            grass: load("grass", 2),
+            dirt: load("ground", 4),
+            tufts: load("tuft", 3),
+        }
+    }
+
+    /// Half the size of the biggest stamp, in pixels
+    fn reach(&self) -> f32 {
+        let stamps = self.grass.iter().chain(&self.dirt).chain(&self.tufts);
+        0.5 * stamps.map(|b| b.width.max(b.height)).max().unwrap_or(0) as f32
+    }
+}
+
+/// The floor of the chunks around the camera, composited once into a bitmap as big as the
+/// chunk at zoom 1
+pub struct GroundCache {
+    chunks: HashMap<ChunkIdx, CachedChunk>,
+    /// Counts `prepare` calls
+    frame: u64,
+    /// Faces the viewer, so lights reach the floor per pixel
+    pub normals: Bitmap,
+    pixels_per_meter: f32,
+    side: usize,
+}
+
+struct CachedChunk {
+    /// `None` without any floor
+    bitmap: Option<Bitmap>,
+    /// `GroundCache::frame` of the last `prepare` it was visible in
+    last_visible: u64,
+}
+
+impl GroundCache {
+    pub fn new(world: &World, pixels_per_meter: f32) -> Self {
+        let side = (world.chunk_side * pixels_per_meter).round() as usize;
+        Self {
+            chunks: HashMap::new(),
+            frame: 0,
+            normals: dome_normals(side, side, 0.0),
+            pixels_per_meter,
+            side,
+        }
+    }
+
+    /// Rebuilds everything on next use, for when the world or the art change
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Once a frame: builds the `visible` chunks that aren't cached, the nearest to `center`
+    /// first, up to `MAX_BUILDS_PER_FRAME` of them. Then drops the chunks seen least recently
+    /// while there are more than `MAX_CACHED_CHUNKS`, never the visible ones
+    pub fn prepare(
+        &mut self, world: &World, art: &GroundArt, visible: impl Iterator<Item = ChunkIdx> + Clone,
+        center: ChunkIdx,
+    ) {
+        self.frame += 1;
+        for idx in visible.clone() {
+            if let Some(chunk) = self.chunks.get_mut(&idx) {
+                chunk.last_visible = self.frame;
+            }
+        }
+
+        let distance = |idx: &ChunkIdx| {
+            (idx.x - center.x).abs() + (idx.y - center.y).abs() + (idx.z - center.z).abs()
+        };
+        let mut builds = 0;
+        while builds < MAX_BUILDS_PER_FRAME {
+            let missing = visible
+                .clone()
+                .filter(|idx| !self.chunks.contains_key(idx))
+                .min_by_key(distance);
+            let idx = match missing {
+                Some(idx) => idx,
+                None => break,
+            };
+            let bitmap = self.composite(world, art, idx);
+            // chunks without floor are next to free
+            if bitmap.is_some() {
+                builds += 1;
+            }
+            let chunk = CachedChunk {
+                bitmap,
+                last_visible: self.frame,
+            };
+            self.chunks.insert(idx, chunk);
+        }
+
+        while self.chunks.len() > MAX_CACHED_CHUNKS {
+            let frame = self.frame;
+            let oldest = self
+                .chunks
+                .iter()
+                .filter(|(_, chunk)| chunk.last_visible != frame)
+                .min_by_key(|(_, chunk)| chunk.last_visible)
+                .map(|(&idx, _)| idx);
+            match oldest {
+                Some(idx) => self.chunks.remove(&idx),
+                None => break,
+            };
+        }
+    }
+
+    /// Only what `prepare` built
+    pub fn get(&self, idx: ChunkIdx) -> Option<&Bitmap> {
+        self.chunks
+            .get(&idx)
+            .and_then(|chunk| chunk.bitmap.as_ref())
+    }
+
+    fn composite(&self, world: &World, art: &GroundArt, idx: ChunkIdx) -> Option<Bitmap> {
+        let tile_pixels = world.tile_side * self.pixels_per_meter;
+        // chunk space is centered on chunk (0, 0), which is also where tile (0, 0) sits
+        let min = world.chunk_side * V2::new(idx.x as f32 - 0.5, idx.y as f32 + 0.5);
+        let tile = |meters: f32| (meters / world.tile_side + 0.5).floor() as i32;
+        let min_tile = (tile(min.x()), tile(min.y() - world.chunk_side));
+        let max_tile = (tile(min.x() + world.chunk_side), tile(min.y()));
+        if !world.has_ground(min_tile, (max_tile.0 + 1, max_tile.1 + 1), idx.z) {
+            return None;
+        }
+
+        let mut bitmap = Bitmap {
+            align_x: 0,
+            align_y: 0,
+            width: self.side,
+            height: self.side,
+            pixels: vec![0; self.side * self.side * 4],
+        };
+        let mut target = RenderTarget::from_bitmap(&mut bitmap);
+        let to_pixels = |x: i32, y: i32| {
+            let p = world.tile_side * V2::new(x as f32, y as f32);
+            self.pixels_per_meter * V2::new(p.x() - min.x(), min.y() - p.y())
+        };
+
+        let half_tile = V2::new(0.5 * tile_pixels, 0.5 * tile_pixels);
+        for y in min_tile.1..=max_tile.1 {
+            for x in min_tile.0..=max_tile.0 {
+                let base = match world.ground_at(x, y, idx.z) {
+                    Some(Ground::Grass) => GRASS_BASE,
+                    Some(Ground::Dirt) => DIRT_BASE,
+                    None => continue,
+                };
+                let center = to_pixels(x, y);
+                target.render_rectangle(center - half_tile, center + half_tile, base);
+            }
+        }
+
+        // stamps of tiles just outside the chunk spill over its edges, drawing them in the
+        // same order from every chunk keeps the seams invisible
+        let margin = (art.reach() / tile_pixels).ceil() as i32;
+        for y in (min_tile.1 - margin..=max_tile.1 + margin).rev() {
+            for x in min_tile.0 - margin..=max_tile.0 + margin {
+                let ground = match world.ground_at(x, y, idx.z) {
+                    Some(ground) => ground,
+                    None => continue,
+                };
+                let mut random = Random::at(x, y, idx.z);
+                let mut stamp = |stamps: &[Bitmap], random: &mut Random| {
+                    let bitmap = &stamps[random.below(stamps.len())];
+                    let jitter = V2::new(random.bilateral(), random.bilateral());
+                    let half = 0.5 * V2::new(bitmap.width as f32, bitmap.height as f32);
+                    let xy = to_pixels(x, y) + 0.5 * tile_pixels * jitter - half;
+                    target.render_bitmap(bitmap, xy, Color::white(), None);
+                };
+                match ground {
+                    Ground::Grass => {
+                        stamp(&art.grass, &mut random);
+                        if random.unilateral() < 0.5 {
+                            stamp(&art.tufts, &mut random);
+                        }
+                    }
+                    Ground::Dirt => stamp(&art.dirt, &mut random),
+                }
+            }
+        }
+
+        Some(bitmap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_a_few_chunks_a_frame_and_drops_unseen_ones() {
+        // 40 tiles across chunks 0 to 2
+        let text = format!("map 0 0\n{}\nend\nplayer 1 0", ".".repeat(40));
+        let world = level::load(&text).unwrap();
+        let dot = || Bitmap {
+            align_x: 0,
+            align_y: 0,
+            width: 1,
+            height: 1,
+            pixels: vec![255; 4],
+        };
+        let art = GroundArt {
+            grass: vec![dot()],
+            dirt: vec![dot()],
+            tufts: vec![dot()],
+        };
+        let mut cache = GroundCache::new(&world, 1.0);
+        let row = (0..3).map(|x| ChunkIdx::new(x, 0, 0));
+        let center = ChunkIdx::new(0, 0, 0);
+        cache.prepare(&world, &art, row.clone(), center);
+        let built =
+            |cache: &GroundCache| row.clone().filter(|&idx| cache.get(idx).is_some()).count();
+        assert_eq!(built(&cache), MAX_BUILDS_PER_FRAME);
+        assert!(cache.get(center).is_some());
+        cache.prepare(&world, &art, row.clone(), center);
+        assert_eq!(built(&cache), 3);
+
+        let empty = (0..MAX_CACHED_CHUNKS as i32 + 8).map(|x| ChunkIdx::new(x, 10, 0));
+        cache.prepare(&world, &art, empty, center);
+        assert_eq!(built(&cache), 0);
+        assert_eq!(cache.chunks.len(), MAX_CACHED_CHUNKS + 8);
+    }
+}
@@ -0,0 +1,636 @@
+//! Hand written levels, loaded instead of a generated dungeon when `GAME_LEVEL` names one.
+//! One directive per line, `;` starts a comment:
+//!
+//! ```text
+//! floor 1                 ; what follows is on floor 1, until the next `floor`
+//! map 0 9                 ; tiles, rows top first, the bottom-left one at tile 0, 9
+//! #########
+//! #...,,,.#
+//! ###.#####
+//! end
+//! player 2 10 hit_points=3
+//! monster 5 10 hit_points=2
+//! familiar 6 10
+//! prop 3 11 kind=tree     ; or rock
+//! torch 4 10 height=2 radius=9 color=1,0.6,0.3
+//! stairs 3 10 length=2 climb=1
+//! ladder 7 10 climb=-1
+//! ```
+//!
+//! Map tiles are `#` a wall on dirt, `%` a wall on grass, `.` dirt, `,` grass, and a space is
+//! nothing. Dirt is dark unless lit by a torch, grass is outdoors. Floors are joined by
+//! `stairs` and `ladder`, each adding both of its ends: the one on `climb` floors away leads
+//! back. Spawns and props have to stand on a map tile that isn't a wall, and there has to be a
+//! player
+
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Both from 1
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn load(text: &str) -> Result<World, ParseError> {
+    let mut parser = Parser {
+        world: World::new(),
+        floor: 0,
+        placed: vec![],
+        player: false,
+    };
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let mut last_line = 1;
+    while let Some((line, text)) = lines.next() {
+        last_line = line;
+        let tokens = tokens(line, text);
+        let (directive, args) = match tokens.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        match directive.text {
+            "floor" => {
+                parser.floor = numbers(directive, args, 1)?[0];
+            }
+            "map" => {
+                let origin = if args.is_empty() {
+                    (0, 0)
+                } else {
+                    let n = numbers(directive, args, 2)?;
+                    (n[0], n[1])
+                };
+                let mut rows = vec![];
+                loop {
+                    match lines.next() {
+                        Some((_, text)) if strip_comment(text).trim() == "end" => break,
+                        Some((line, text)) => rows.push((line, strip_comment(text))),
+                        None => return Err(directive.error("map without an `end`")),
+                    }
+                }
+                parser.add_map(origin, &rows)?;
+            }
+            _ => parser.add_thing(directive, args)?,
+        }
+    }
+
+    if !parser.player {
+        let message = "no player".to_owned();
+        return Err(ParseError {
+            line: last_line,
+            column: 1,
+            message,
+        });
+    }
+    parser.check_placed()?;
+    Ok(parser.world)
+}
+
+struct Parser<'a> {
+    world: World,
+    floor: i32,
+    /// Tiles that have to be open floor, with what stands there, checked once every map is in
+    placed: Vec<((i32, i32, i32), Token<'a>)>,
+    player: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn add_map(
+        &mut self, (min_x, min_y): (i32, i32), rows: &[(usize, &str)],
+    ) -> Result<(), ParseError> {
+        let z = self.floor;
+        for (i, &(line, row)) in rows.iter().enumerate() {
+            let y = min_y + (rows.len() - 1 - i) as i32;
+            let mut tiles = vec![];
+            for (j, c) in row.chars().enumerate() {
+                let (ground, wall) = match c {
+                    '#' => (Ground::Dirt, true),
+                    '%' => (Ground::Grass, true),
+                    '.' => (Ground::Dirt, false),
+                    ',' => (Ground::Grass, false),
+                    ' ' => {
+                        tiles.push(None);
+                        continue;
+                    }
+                    _ => {
+                        let message = format!("unknown tile `{}`", c);
+                        return Err(ParseError {
+                            line,
+                            column: j + 1,
+                            message,
+                        });
+                    }
+                };
+                tiles.push(Some(MapTile { ground, wall }));
+            }
+            self.world.add_row((min_x, y, z), &tiles);
+        }
+        Ok(())
+    }
+
+    fn add_thing(&mut self, directive: &Token<'a>, args: &[Token<'a>]) -> Result<(), ParseError> {
+        let keys: &[&str] = match directive.text {
+            "player" | "monster" => &["hit_points"],
+            "familiar" => &[],
+            "prop" => &["kind"],
+            "torch" => &["height", "radius", "color"],
+            "stairs" => &["length", "climb"],
+            "ladder" => &["climb"],
+            _ => {
+                let message = format!("unknown directive `{}`", directive.text);
+                return Err(directive.error(&message));
+            }
+        };
+        let (position, options) = args.split_at(args.len().min(2));
+        let n = numbers(directive, position, 2)?;
+        let (x, y, z) = (n[0], n[1], self.floor);
+        let tile = (x, y, z);
+        let options = options
+            .iter()
+            .map(|token| option(token, keys))
+            .collect::<Result<Vec<_>, _>>()?;
+        let get = |key: &str| options.iter().find(|o| o.0 == key);
+
+        let hit_points = |default: usize| match get("hit_points") {
+            Some((_, token)) => match token.text.parse() {
+                Ok(n) if n > 0 && n <= MAX_HIT_POINTS => Ok(n),
+                _ => Err(token.error(&format!(
+                    "hit points have to be a whole number from 1 to {}",
+                    MAX_HIT_POINTS
+                ))),
+            },
+            None => Ok(default),
+        };
+        let placed = (tile, directive.clone());
+        match directive.text {
+            "player" => {
+                let hit_points = hit_points(PLAYER_HIT_POINTS)?;
+                self.world.spawns.push(Spawn::Player { tile, hit_points });
+                self.placed.push(placed);
+                self.player = true;
+            }
+            "monster" => {
+                let hit_points = hit_points(MONSTER_HIT_POINTS)?;
+                self.world.spawns.push(Spawn::Monster { tile, hit_points });
+                self.placed.push(placed);
+            }
+            "familiar" => {
+                self.world.spawns.push(Spawn::Familiar { tile });
+                self.placed.push(placed);
+            }
+            "prop" => {
+                let prop = match get("kind") {
+                    Some((_, token)) => prop(token)?,
+                    None => return Err(directive.error("`prop` needs a kind=")),
+                };
+                self.world.spawns.push(Spawn::Prop { tile, prop });
+                self.placed.push(placed);
+            }
+            "torch" => {
+                let height = match get("height") {
+                    Some((_, token)) => float(token)?,
+                    None => TORCH_HEIGHT,
+                };
+                let radius = match get("radius") {
+                    Some((_, token)) => float(token)?,
+                    None => TORCH_RADIUS,
+                };
+                let color = match get("color") {
+                    Some((_, token)) => color(token)?,
+                    None => TORCHES[0],
+                };
+                self.world.add_torch(tile, height, color, radius);
+            }
+            _ => {
+                let kind = if directive.text == "ladder" {
+                    StairsKind::Ladder
+                } else {
+                    StairsKind::Stairs
+                };
+                let length = match get("length") {
+                    Some((_, token)) => match token.text.parse() {
+                        Ok(n) if n > 0 && n <= MAX_STAIRS_LENGTH => n,
+                        _ => {
+                            let message = format!(
+                                "length has to be a whole number from 1 to {}",
+                                MAX_STAIRS_LENGTH
+                            );
+                            return Err(token.error(&message));
+                        }
+                    },
+                    None if kind == StairsKind::Ladder => 1,
+                    None => STAIRS_LENGTH,
+                };
+                let climb = match get("climb") {
+                    Some((_, token)) => match token.text.parse() {
+                        Ok(n) if n != 0 => n,
+                        _ => return Err(token.error("climb has to be a whole number but 0")),
+                    },
+                    None => 1,
+                };
+                self.world.add_stairs(kind, tile, length, climb);
+                for &z in &[z, z + climb] {
+                    for i in 0..length {
+                        self.placed.push(((x, y + i, z), directive.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_placed(&self) -> Result<(), ParseError> {
+        for (tile, token) in &self.placed {
+            if let Some(problem) = misplaced(&self.world, *tile) {
+                return Err(token.error(&format!("{} {}", token.text, problem)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why nothing can stand on `tile`, if it can't
+pub fn misplaced(world: &World, tile: (i32, i32, i32)) -> Option<String> {
+    let (x, y, z) = tile;
+    let problem = if world.walls.contains(&tile) {
+        "in a wall"
+    } else if world.ground_at(x, y, z).is_none() {
+        "off the map"
+    } else {
+        return None;
+    };
+    Some(format!("{} at {}, {} of floor {}", problem, x, y, z))
+}
+
+/// A level the way it is written, tile by tile, for `editor` to change and save
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub tiles: BTreeMap<(i32, i32, i32), MapTile>,
+    pub spawns: Vec<Spawn>,
+    pub torches: Vec<Torch>,
+    /// Only the ends going up, `build` adds the ones leading back
+    pub stairs: Vec<Stairs>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Torch {
+    pub tile: (i32, i32, i32),
+    pub height: f32,
+    pub color: Color,
+    pub radius: f32,
+}
+
+impl Level {
+    pub fn from_world(world: &World) -> Self {
+        let torches = world
+            .lights
+            .iter()
+            .map(|light| Torch {
+                tile: world.tile_at(light.p),
+                height: light.z,
+                color: light.color,
+                radius: light.radius,
+            })
+            .collect();
+        Self {
+            tiles: world.map_tiles(),
+            spawns: world.spawns.clone(),
+            torches,
+            stairs: world
+                .stairs
+                .iter()
+                .filter(|s| s.climb > 0)
+                .copied()
+                .collect(),
+        }
+    }
+
+    pub fn build(&self) -> World {
+        let mut result = World::new();
+        let mut rows: BTreeMap<(i32, i32), Vec<(i32, MapTile)>> = BTreeMap::new();
+        for (&(x, y, z), &tile) in &self.tiles {
+            rows.entry((z, y)).or_default().push((x, tile));
+        }
+        for ((z, y), row) in rows {
+            // sorted by x, as `tiles` is
+            let min_x = row[0].0;
+            let mut tiles = vec![None; (row[row.len() - 1].0 - min_x + 1) as usize];
+            for (x, tile) in row {
+                tiles[(x - min_x) as usize] = Some(tile);
+            }
+            result.add_row((min_x, y, z), &tiles);
+        }
+        result.spawns = self.spawns.clone();
+        for torch in &self.torches {
+            result.add_torch(torch.tile, torch.height, torch.color, torch.radius);
+        }
+        for stairs in &self.stairs {
+            result.add_stairs(stairs.kind, stairs.tile, stairs.length, stairs.climb);
+        }
+        result
+    }
+
+    /// What `load` reads back, floor by floor
+    pub fn to_text(&self) -> String {
+        let mut floors: Vec<i32> = self.tiles.keys().map(|t| t.2).collect();
+        floors.extend(self.spawns.iter().map(|s| s.tile().2));
+        floors.extend(self.torches.iter().map(|t| t.tile.2));
+        floors.extend(self.stairs.iter().map(|s| s.tile.2));
+        floors.sort_unstable();
+        floors.dedup();
+        let mut result = String::new();
+        for z in floors {
+            result += &format!("floor {}\n", z);
+            let on_floor: Vec<_> = self.tiles.keys().filter(|t| t.2 == z).collect();
+            if let (Some(min_x), Some(max_x), Some(min_y), Some(max_y)) = (
+                on_floor.iter().map(|t| t.0).min(),
+                on_floor.iter().map(|t| t.0).max(),
+                on_floor.iter().map(|t| t.1).min(),
+                on_floor.iter().map(|t| t.1).max(),
+            ) {
+                result += &self.map_text((min_x, min_y), (max_x, max_y), z);
+            }
+            for spawn in self.spawns.iter().filter(|s| s.tile().2 == z) {
+                let (x, y, _) = spawn.tile();
+                result += &match spawn {
+                    Spawn::Player { hit_points, .. } => {
+                        format!("player {} {} hit_points={}\n", x, y, hit_points)
+                    }
+                    Spawn::Monster { hit_points, .. } => {
+                        format!("monster {} {} hit_points={}\n", x, y, hit_points)
+                    }
+                    Spawn::Familiar { .. } => format!("familiar {} {}\n", x, y),
+                    Spawn::Prop { prop, .. } => format!("prop {} {} kind={}\n", x, y, prop.name()),
+                };
+            }
+            for torch in self.torches.iter().filter(|t| t.tile.2 == z) {
+                let (x, y, _) = torch.tile;
+                let c = torch.color;
+                result += &format!(
+                    "torch {} {} height={} radius={} color={},{},{}\n",
+                    x, y, torch.height, torch.radius, c.red, c.green, c.blue
+                );
+            }
+            for stairs in self.stairs.iter().filter(|s| s.tile.2 == z) {
+                let (x, y, _) = stairs.tile;
+                result += &match stairs.kind {
+                    StairsKind::Stairs => format!(
+                        "stairs {} {} length={} climb={}\n",
+                        x, y, stairs.length, stairs.climb
+                    ),
+                    StairsKind::Ladder => format!("ladder {} {} climb={}\n", x, y, stairs.climb),
+                };
+            }
+            result += "\n";
+        }
+        result
+    }
+
+    /// `map` block of floor `z` from `min` to `max`, both included
+    fn map_text(&self, (min_x, min_y): (i32, i32), (max_x, max_y): (i32, i32), z: i32) -> String {
+        let mut result = format!("map {} {}\n", min_x, min_y);
+        for y in (min_y..=max_y).rev() {
+            let row: String = (min_x..=max_x)
+                .map(|x| match self.tiles.get(&(x, y, z)) {
+                    Some(MapTile { ground, wall }) => match (ground, wall) {
+                        (Ground::Dirt, true) => '#',
+                        (Ground::Grass, true) => '%',
+                        (Ground::Dirt, false) => '.',
+                        (Ground::Grass, false) => ',',
+                    },
+                    None => ' ',
+                })
+                .collect();
+            result += row.trim_end();
+            result += "\n";
+        }
+        result += "end\n";
+        result
+    }
+}
+
+/// Word of a line and where it starts
+#[derive(Clone, Debug, PartialEq)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.to_owned(),
+        }
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split(';').next().unwrap_or("")
+}
+
+fn tokens(line: usize, text: &str) -> Vec<Token<'_>> {
+    let text = strip_comment(text);
+    let mut result = vec![];
+    let mut start = None;
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                result.push(Token {
+                    text: &text[s..i],
+                    line,
+                    column: text[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Exactly `count` whole numbers after `directive`
+fn numbers(directive: &Token, args: &[Token], count: usize) -> Result<Vec<i32>, ParseError> {
+    if args.len() != count {
+        let message = format!("`{}` takes {} whole numbers", directive.text, count);
+        return Err(args.get(count).unwrap_or(directive).error(&message));
+    }
+    args.iter()
+        .map(|token| {
+            token
+                .text
+                .parse()
+                .map_err(|_| token.error("expected a whole number"))
+        })
+        .collect()
+}
+
+/// `key=value`, `key` being one of `keys`
+fn option<'a>(token: &Token<'a>, keys: &[&str]) -> Result<(&'a str, Token<'a>), ParseError> {
+    let mut split = token.text.splitn(2, '=');
+    let key = split.next().unwrap_or("");
+    let value = match split.next() {
+        Some(value) if !value.is_empty() => value,
+        _ => return Err(token.error("expected `key=value`")),
+    };
+    if !keys.contains(&key) {
+        let message = if keys.is_empty() {
+            format!("unknown option `{}`, there are none", key)
+        } else {
+            format!("unknown option `{}`, expected {}", key, keys.join(", "))
+        };
+        return Err(token.error(&message));
+    }
+    let value = Token {
+        text: value,
+        line: token.line,
+        column: token.column + key.chars().count() + 1,
+    };
+    Ok((key, value))
+}
+
+fn float(token: &Token) -> Result<f32, ParseError> {
+    match token.text.parse::<f32>() {
+        Ok(f) if f >= 0.0 => Ok(f),
+        _ => Err(token.error("expected a number, 0 or more")),
+    }
+}
+
+fn prop(token: &Token) -> Result<Prop, ParseError> {
+    match Prop::ALL.iter().find(|p| p.name() == token.text) {
+        Some(&prop) => Ok(prop),
+        None => {
+            let names: Vec<_> = Prop::ALL.iter().map(|p| p.name()).collect();
+            Err(token.error(&format!("expected a kind of prop: {}", names.join(", "))))
+        }
+    }
+}
+
+/// `red,green,blue`, each from 0 to 1
+fn color(token: &Token) -> Result<Color, ParseError> {
+    let channels = token
+        .text
+        .split(',')
+        .map(|c| c.parse::<f32>().ok().filter(|c| (0.0..=1.0).contains(c)))
+        .collect::<Option<Vec<_>>>();
+    match channels.as_deref() {
+        Some(&[red, green, blue]) => Ok(Color {
+            red,
+            green,
+            blue,
+            alpha: 1.0,
+        }),
+        _ => Err(token.error("expected a color as red,green,blue, each from 0 to 1")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = "
+; two floors
+map
+#######
+#..,,.#
+#######
+end
+player 1 1 hit_points=5
+monster 4 1
+stairs 5 1 length=1
+
+floor 1
+map 3 0
+ ####
+ #.. ; a hole in the wall
+ ####
+end
+familiar 5 1
+prop 6 1 kind=rock
+torch 6 1 color=1,0.5,0
+";
+
+    #[test]
+    fn loads_tiles_spawns_and_stairs() {
+        let world = load(LEVEL).unwrap();
+        assert_eq!(world.walls.len(), 7 + 2 + 7 + 4 + 1 + 4);
+        assert!(world.walls.contains(&(4, 2, 1)));
+        assert_eq!(world.ground_at(3, 1, 0), Some(Ground::Grass));
+        assert_eq!(world.ground_at(2, 1, 0), Some(Ground::Dirt));
+        assert_eq!(world.ground_at(7, 1, 1), None);
+        assert_eq!(
+            world.spawns,
+            vec![
+                Spawn::Player {
+                    tile: (1, 1, 0),
+                    hit_points: 5
+                },
+                Spawn::Monster {
+                    tile: (4, 1, 0),
+                    hit_points: MONSTER_HIT_POINTS
+                },
+                Spawn::Familiar { tile: (5, 1, 1) },
+                Spawn::Prop {
+                    tile: (6, 1, 1),
+                    prop: Prop::Rock
+                },
+            ]
+        );
+        let ends: Vec<_> = world.stairs.iter().map(|s| (s.tile, s.climb)).collect();
+        assert_eq!(ends, vec![((5, 1, 0), 1), ((5, 1, 1), -1)]);
+        assert_eq!(world.lights.len(), 1);
+    }
+
+    #[test]
+    fn written_levels_load_back_the_same() {
+        let level = Level::from_world(&load(LEVEL).unwrap());
+        let written = level.to_text();
+        let reloaded = Level::from_world(&load(&written).unwrap());
+        assert_eq!(reloaded, level, "{}", written);
+        assert_eq!(level.torches[0].tile, (6, 1, 1));
+
+        let mut generated = Level::from_world(&World::generate(3));
+        let reloaded = Level::from_world(&load(&generated.to_text()).unwrap());
+        // written floor by floor
+        generated.spawns.sort_by_key(|s| s.tile().2);
+        assert_eq!(reloaded, generated);
+    }
+
+    #[test]
+    fn errors_tell_where() {
+        let error = |text: &str| {
+            let e = load(text).unwrap_err();
+            (e.line, e.column)
+        };
+        assert_eq!(error("map\n#.\n#x\nend\nplayer 1 1"), (3, 2));
+        assert_eq!(error("map\n#..\nend\nplayer 1 0 hp=3"), (4, 12));
+        assert_eq!(error("map\n#..\nend\n  player 0 0"), (4, 3));
+        assert_eq!(error("map\n#..\nend\nplayer 1 0\nmonster 9 0"), (5, 1));
+        assert_eq!(error("map\n#..\nend\nmonster 1 0"), (4, 1));
+        assert_eq!(
+            error("map\n#..\nend\nplayer 1 0\nstairs 2 0 length=9"),
+            (5, 19)
+        );
+        assert_eq!(error("map\n#..\nend\nplayer 1 0 hit_points=13"), (4, 23));
+        assert_eq!(error("map\n#..\n"), (1, 1));
+        assert_eq!(error("floor one"), (1, 7));
+        assert_eq!(
+            error("map\n#..\nend\nplayer 1 0\nprop 2 0 kind=bush"),
+            (5, 15)
+        );
+    }
+}
@@ -1,20 +1,218 @@
+#[macro_use]
+mod log;
+
+mod animation;
+mod camera;
+mod capture;
+mod debug;
+mod debug_draw;
+mod dungeon;
+mod editor;
 mod entity;
+#[cfg(test)]
+mod golden;
+mod ground;
 #[path = "../../src/host_api.rs"]
 mod host_api;
+mod json;
+mod level;
 mod math;
+mod memory;
+mod particles;
 mod render;
+mod render_group;
+mod tiled;
 mod world;
 
 pub use math::*;
 pub use render::OffscreenBuffer;
 
+use animation::*;
+use camera::*;
+use capture::*;
+use debug::*;
+use dungeon::*;
+use editor::*;
 use entity::*;
+use ground::*;
 use host_api::*;
-use render::Color;
+use memory::*;
+use particles::*;
+use render::{dome_normals, Color, Light, Palette, Quad};
+use render_group::*;
 use world::*;
 
+#[cfg(not(test))]
+#[global_allocator]
+static ALLOCATOR: GameAllocator = GameAllocator;
+
+const MAX_ENTITY_PIECES: usize = 16;
+/// Screen size of a tile at zoom 1
+const TILE_SIDE_IN_PIXELS: f32 = 60.0;
+/// Shadows shrink and fade to 1 / (1 + this * z) for an entity `z` meters up
+const SHADOW_FADE_PER_METER: f32 = 0.5;
+/// Resting height of the familiar, in meters
+const FAMILIAR_HOVER: f32 = 0.5;
+/// How far a thrown sword flies, in meters
+const SWORD_DISTANCE: f32 = 5.0;
+/// Peaks at 1 meter halfway
+const SWORD_ARC: f32 = 4.0 / (SWORD_DISTANCE * SWORD_DISTANCE);
+
+/// How long the hero can't be hurt again after a monster touched it, in seconds
+const HURT_TIME: f32 = 1.0;
+/// The hurt hero flashes white every other this many seconds
+const HURT_FLASH_PERIOD: f32 = 0.1;
+/// Monsters closer than this to the hero, edge to edge, hurt it
+const TOUCH_DISTANCE: f32 = 0.1;
+
+/// Drawn for a flight of stairs, whatever its length
+const STAIRS_STEPS: usize = 8;
+
+/// Entities of the floors next to the camera's are drawn see-through
+const FLOOR_ABOVE_ALPHA: f32 = 0.3;
+const FLOOR_BELOW_ALPHA: f32 = 0.5;
+
+/// Bitmaps of `GameState::particles`
+const PARTICLE_DOT: usize = 0;
+
+/// Kicked up by the hero's feet
+static DUST: ParticleSpec = ParticleSpec {
+    lifetime: (0.4, 0.7),
+    direction: std::f32::consts::FRAC_PI_2,
+    spread: std::f32::consts::PI,
+    speed: (0.3, 0.8),
+    dz: (0.5, 1.0),
+    ddp: V2::new(0.0, 0.0),
+    ddz: -4.0,
+    drag: 2.0,
+    color: &[(
+        0.0,
+        Color {
+            red: 0.6,
+            green: 0.5,
+            blue: 0.4,
+            alpha: 1.0,
+        },
+    )],
+    alpha: &[(0.0, 0.6), (1.0, 0.0)],
+    appearance: Appearance::Bitmap {
+        index: PARTICLE_DOT,
+        size: 0.35,
+    },
+};
+
+/// A sword striking a wall
+static SPARKS: ParticleSpec = ParticleSpec {
+    lifetime: (0.2, 0.5),
+    direction: 0.0,
+    spread: std::f32::consts::PI,
+    speed: (2.0, 5.0),
+    dz: (1.0, 3.0),
+    ddp: V2::new(0.0, 0.0),
+    ddz: -9.8,
+    drag: 1.0,
+    color: &[
+        (
+            0.0,
+            Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 0.8,
+                alpha: 1.0,
+            },
+        ),
+        (
+            1.0,
+            Color {
+                red: 1.0,
+                green: 0.4,
+                blue: 0.1,
+                alpha: 1.0,
+            },
+        ),
+    ],
+    alpha: &[(0.0, 1.0), (0.7, 1.0), (1.0, 0.0)],
+    appearance: Appearance::Rect(V2::new(0.06, 0.06)),
+};
+
+/// Left behind by the familiar
+static MAGIC_TRAIL: ParticleSpec = ParticleSpec {
+    lifetime: (0.6, 1.0),
+    direction: std::f32::consts::FRAC_PI_2,
+    spread: std::f32::consts::PI,
+    speed: (0.0, 0.2),
+    dz: (0.2, 0.5),
+    ddp: V2::new(0.0, 0.0),
+    ddz: 0.0,
+    drag: 0.5,
+    color: &[
+        (
+            0.0,
+            Color {
+                red: 0.6,
+                green: 0.8,
+                blue: 1.0,
+                alpha: 1.0,
+            },
+        ),
+        (
+            1.0,
+            Color {
+                red: 0.8,
+                green: 0.4,
+                blue: 1.0,
+                alpha: 1.0,
+            },
+        ),
+    ],
+    alpha: &[(0.0, 0.0), (0.2, 0.8), (1.0, 0.0)],
+    appearance: Appearance::Bitmap {
+        index: PARTICLE_DOT,
+        size: 0.25,
+    },
+};
+
+/// Rising from the dungeon torches
+static EMBERS: ParticleSpec = ParticleSpec {
+    lifetime: (0.8, 1.5),
+    direction: std::f32::consts::FRAC_PI_2,
+    spread: std::f32::consts::PI,
+    speed: (0.0, 0.3),
+    dz: (0.8, 1.5),
+    ddp: V2::new(0.0, 0.0),
+    ddz: 0.0,
+    drag: 0.2,
+    color: &[
+        (
+            0.0,
+            Color {
+                red: 1.0,
+                green: 0.7,
+                blue: 0.3,
+                alpha: 1.0,
+            },
+        ),
+        (
+            1.0,
+            Color {
+                red: 0.8,
+                green: 0.2,
+                blue: 0.1,
+                alpha: 1.0,
+            },
+        ),
+    ],
+    alpha: &[(0.0, 1.0), (1.0, 0.0)],
+    appearance: Appearance::Rect(V2::new(0.05, 0.05)),
+};
+
 #[no_mangle]
-pub extern "C" fn game_init(host_api: &dyn HostApi) -> *mut GameState {
+pub extern "C" fn game_init(memory: &mut GameMemory, host_api: &dyn HostApi) -> *mut GameState {
+    // the host keeps `memory` alive for as long as the game runs
+    let heap = unsafe { Heap::new(&mut memory.permanent) };
+    use_heap(heap);
+    let transient = unsafe { Arena::new(&mut memory.transient) };
+
     let width = 1920 / 2;
     let height = 1080 / 2;
     let bytes_per_pixel = 4;
@@ -25,40 +223,97 @@ pub extern "C" fn game_init(host_api: &dyn HostApi) -> *mut GameState {
         bytes_per_pixel,
     };
     let world = World::new();
-    let camera = world.initial_camera();
+    let pixels_per_meter = TILE_SIDE_IN_PIXELS / world.tile_side;
+    let camera = Camera::new(
+        world.initial_player(),
+        pixels_per_meter,
+        V2::new(width as f32, height as f32),
+    );
+    let ground = GroundCache::new(&world, pixels_per_meter);
+    let tree = load_bmp(host_api, "assets/test2/tree00.bmp");
     let mut state = GameState {
         offscreen_buffer,
         world,
         camera,
         entity_focused_by_camera: None,
         storage: Storage::default(),
-        backdrop: host_api.load_bmp("assets/test/test_background.bmp"),
-        shadow: host_api.load_bmp("assets/test/test_hero_shadow.bmp"),
-        tree: host_api.load_bmp("assets/test2/tree00.bmp"),
+        ground,
+        ground_art: GroundArt::load(host_api),
+        shadow: load_bmp(host_api, "assets/test/test_hero_shadow.bmp"),
+        tree_normals: dome_normals(tree.width, tree.height, 0.8),
+        tree,
+        sword: load_bmp(host_api, "assets/test2/rock03.bmp"),
+        hero_flash: Palette::default(),
         hero_bitmaps: load_hero(host_api),
+        hero_animations: hero_animations(),
+        particles: ParticleSystem::new(vec![soft_dot(16)]),
+        debug: DebugState::default(),
+        editor: Editor::default(),
+        capture: Capture::default(),
+        heap,
+        transient,
     };
+    let hero_pieces: Vec<&Bitmap> = state
+        .hero_bitmaps
+        .iter()
+        .flat_map(|h| vec![&h.torso, &h.cape, &h.head])
+        .collect();
+    state.hero_flash = Palette::flash(&hero_pieces, Color::white());
     state.shadow.align_x = 72;
     state.shadow.align_y = 182;
     state.tree.align_x = 40;
     state.tree.align_y = 80;
-    state.start();
-    Box::into_raw(Box::new(state))
+    // spins around its center
+    state.sword.align_x = state.sword.width as u32 / 2;
+    state.sword.align_y = state.sword.height as u32 / 2;
+    state.start(host_api);
+    Box::leak(Box::new(state))
 }
 
 #[no_mangle]
-pub extern "C" fn game_restart(state: &mut GameState) {
-    state.start();
+pub extern "C" fn game_restart(state: &mut GameState, host_api: &dyn HostApi) {
+    use_heap(state.heap);
+    state.start(host_api);
 }
 
 #[no_mangle]
 pub extern "C" fn game_update(
     state: &mut GameState, input: &Input, host_api: &mut dyn HostApi,
 ) -> bool {
-    let tile_side_in_pixels: f32 = 60.0;
-    let meters_to_pixels = tile_side_in_pixels as f32 / state.tile_side();
-    let screen_center_x = 0.5 * (state.offscreen_buffer.width as f32);
-    let screen_center_y = 0.5 * (state.offscreen_buffer.height as f32);
+    use_heap(state.heap);
+    state.transient.reset();
     state.offscreen_buffer.reset();
+    state.debug.toggle(input);
+    debug_draw::begin(state.debug.enabled);
+    state.camera.update_zoom(input);
+    if let Some(world) = state.editor.toggle(input, &mut state.camera, &state.world) {
+        state.start_on(world, host_api);
+    }
+    if state.editor.enabled() {
+        state
+            .editor
+            .update(input, &mut state.camera, &state.world, host_api);
+        state.editor.draw(
+            &mut state.offscreen_buffer,
+            input,
+            &state.camera,
+            &state.world,
+        );
+        state
+            .capture
+            .update(input, &state.offscreen_buffer, host_api);
+        host_api.update_canvas(
+            &state.offscreen_buffer.buffer,
+            state.offscreen_buffer.pitch(),
+        );
+        return true;
+    }
+    let camera = state.camera;
+    let debug = state.debug.enabled;
+    let debug_view = DebugView {
+        camera,
+        chunk_side: state.world.chunk_side,
+    };
 
     //move player
     let player_ddp = {
@@ -83,211 +338,440 @@ pub extern "C" fn game_update(
     // TODO check for some keypress, and create a sword
     // TODO multiplayer
 
+    // zooming out shows more of the world, which has to keep moving
     let updatable_bounds = {
         let tile_span_x = 17 * 3;
         let tile_span_y = 9 * 3;
-        let camera_size = state.tile_side() * V2::new(tile_span_x as f32, tile_span_y as f32);
+        let camera_size =
+            (state.tile_side() / camera.zoom) * V2::new(tile_span_x as f32, tile_span_y as f32);
 
         Rect2::new_center_dim(V2::default(), camera_size)
     };
+
+    // lights live in the world, which the sim region borrows until the end of the frame
+    let ambient = state.world.ambient_at(camera.p);
+    let floor_height = state.world.floor_height;
+    let mut lights = state.transient.stack(state.world.lights.len());
+    for light in &state.world.lights {
+        let diff = state.world.substract(light.p, camera.p);
+        if diff.z != 0.0 || !updatable_bounds.contains(diff.xy) {
+            continue;
+        }
+        debug_draw::circle(diff.xy, light.radius, light.color);
+        lights.push(Light {
+            p: camera.to_screen(diff.xy),
+            height: camera.meters_to_pixels() * light.z,
+            color: light.color,
+            radius: camera.meters_to_pixels() * light.radius,
+        });
+    }
+
+    // so is the floor under the screen, built a few chunks a frame as it shows up, and the one
+    // below it, seen through the holes
+    let ground_chunks = {
+        let half = (1.0 / camera.meters_to_pixels()) * camera.screen_center();
+        // the floor below is drawn lower on the screen
+        let min = state
+            .world
+            .map_into_chunk_space(camera.p, -half - V2::new(0.0, floor_height))
+            .abs;
+        let max = state.world.map_into_chunk_space(camera.p, half).abs;
+        let count = 2 * (max.x - min.x + 1) * (max.y - min.y + 1);
+        let mut chunks = state.transient.stack(count as usize);
+        for z in camera.p.abs.z - 1..=camera.p.abs.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let idx = ChunkIdx::new(x, y, z);
+                    let center = WorldPosition {
+                        abs: idx,
+                        offset: V2::default(),
+                    };
+                    let half_chunk = 0.5 * state.world.chunk_side;
+                    let diff = state.world.substract(center, camera.p);
+                    let top_left = camera.to_screen(diff.xy + V2::new(-half_chunk, half_chunk))
+                        + camera.lift(diff.z);
+                    chunks.push((idx, top_left));
+                }
+            }
+        }
+        let visible = chunks.iter().map(|&(idx, _)| idx);
+        let (world, art) = (&state.world, &state.ground_art);
+        state.ground.prepare(world, art, visible, camera.p.abs);
+        chunks
+    };
+
+    state
+        .particles
+        .recenter(&state.world, camera.p, updatable_bounds);
+
     let mut sim_region = SimRegion::new(
         &mut state.storage,
         &mut state.world,
-        state.camera,
+        &state.transient,
+        camera.p,
         updatable_bounds,
     );
     sim_region.begin();
+    trace!(
+        host_api,
+        "sim",
+        "simming {} entities around {:?}",
+        sim_region.entities.len(),
+        sim_region.origin
+    );
 
-    // clear bg with magenta
-    {
-        let grey = Color {
-            red: 0.5,
-            green: 0.5,
-            blue: 0.5,
+    let mut render_group = RenderGroup::new(
+        &state.transient,
+        1 + ground_chunks.len() + sim_region.entities.len() * MAX_ENTITY_PIECES + MAX_PARTICLES,
+    );
+    render_group.clear(Color {
+        red: 0.5,
+        green: 0.5,
+        blue: 0.5,
+        alpha: 1.0,
+    });
+    for &(idx, top_left) in ground_chunks.iter() {
+        let bitmap = match state.ground.get(idx) {
+            Some(bitmap) => bitmap,
+            None => continue,
         };
-        state.offscreen_buffer.render_rectangle(
-            V2::default(),
-            V2::new(
-                state.offscreen_buffer.width as f32,
-                state.offscreen_buffer.height as f32,
-            ),
-            grey,
-        );
+        let floor = idx.z - camera.p.abs.z;
+        let key = SortKey::background().on_floor(floor);
+        let quad = Quad {
+            origin: top_left,
+            x_axis: V2::new(camera.zoom * bitmap.width as f32, 0.0),
+            y_axis: V2::new(0.0, camera.zoom * bitmap.height as f32),
+        };
+        let normals = Some(&state.ground.normals);
+        if floor != 0 {
+            let color = Color::white().with_alpha(floor_alpha(floor));
+            render_group.push_quad(key, bitmap, None, quad, color, None);
+        } else if (camera.zoom - 1.0).abs() <= std::f32::EPSILON {
+            render_group.push_bitmap_with(key, bitmap, normals, top_left, Color::white(), None);
+        } else {
+            render_group.push_quad(key, bitmap, normals, quad, Color::white(), None);
+        }
+    }
+    render_group.set_ambient(ambient);
+    // the render group takes so many, the ones nearest to the middle of the screen
+    let center = camera.screen_center();
+    lights.sort_unstable_by(|a, b| {
+        let a = (a.p - center).len_sq();
+        let b = (b.p - center).len_sq();
+        a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    for light in lights.into_iter().take(MAX_LIGHTS) {
+        render_group.push_light(light);
     }
 
     {
-        let mut new_entities = vec![];
-        for (_, entity) in sim_region.entities.iter() {
+        let mut new_entities = state.transient.stack(2 * sim_region.entities.len());
+        for entity in sim_region.entities.values() {
             if !entity.updatable {
                 continue;
             }
-            let mut entity_pieces = vec![];
+            let mut entity_pieces = state.transient.stack(MAX_ENTITY_PIECES);
             let hero_bitmaps = &state.hero_bitmaps[entity.facing_direction];
 
-            let debug = true;
             match entity.kind {
                 EntityKind::Player => {
+                    let mut animation = entity.animation;
                     if input.new.sword {
+                        animation.play(ClipId::Attack);
+                    } else if animation.clip() != ClipId::Attack || animation.finished() {
+                        if player_ddp.len_sq() > 0.0 {
+                            animation.play(ClipId::Walk);
+                        } else {
+                            animation.play(ClipId::Idle);
+                        }
+                    }
+                    let mut strike = false;
+                    let mut footstep = false;
+                    let dt = input.time_per_frame;
+                    animation.advance(&state.hero_animations, dt, |event| match event {
+                        AnimationEvent::Footstep => footstep = true,
+                        AnimationEvent::Strike => strike = true,
+                    });
+                    if footstep {
+                        state.particles.emit(&DUST, entity.p, 0.0, 4);
+                    }
+                    let nudge = animation.frame(&state.hero_animations).offset;
+
+                    if strike {
                         let sword_idx = entity.sword.unwrap();
                         let sword = sim_region.entities.get(&sword_idx).unwrap();
                         if !sword.spatial {
                             let mut sword = sword.clone();
                             sword.spatial = true;
-                            sword.distance_remaining = 5.0;
+                            sword.distance_remaining = SWORD_DISTANCE;
                             sword.p = entity.p;
+                            sword.chunk_z = entity.chunk_z;
                             sword.dp = 2.0 * V2::new(1.0, 1.0);
+                            debug!(host_api, "sim", "sword {:?} thrown", sword.idx);
                             new_entities.push(sword);
                         }
                     }
                     let size = V2::new(entity.width, entity.height);
                     if debug {
-                        let offset = 0.5 * size;
                         entity_pieces.push(EntityVisiblePiece::new_rect(
                             Color::magenta(),
                             size,
-                            offset,
+                            V2::default(),
                             1.0,
                             1.0,
                         ));
                     }
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(&state.shadow, 1.0, 1.0));
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(
-                        &hero_bitmaps.torso,
-                        1.0,
-                        1.0,
-                    ));
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(
-                        &hero_bitmaps.cape,
-                        1.0,
-                        1.0,
-                    ));
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(
-                        &hero_bitmaps.head,
-                        1.0,
-                        1.0,
-                    ));
-
-                    // health points
-                    {
-                        let health_dim = V2::new(0.2, 0.2);
-                        let spacing_x = 1.5 * health_dim.x();
-                        let mut hit_p = V2::new(
-                            -0.5 * (entity.hit_points.len() - 1) as f32 * spacing_x,
-                            -0.25,
-                        );
-                        let dhit_p = V2::new(spacing_x, 0.0);
-                        for _ in &entity.hit_points {
-                            entity_pieces.push(EntityVisiblePiece::new_rect(
-                                Color::red(),
-                                health_dim,
-                                hit_p,
-                                1.0,
-                                0.0,
-                            ));
-                            hit_p += dhit_p;
+                    entity_pieces.push(EntityVisiblePiece::new_shadow(&state.shadow));
+                    let flash =
+                        entity.hurt > 0.0 && (entity.hurt / HURT_FLASH_PERIOD) as i32 % 2 == 0;
+                    for bitmap in &[&hero_bitmaps.torso, &hero_bitmaps.cape, &hero_bitmaps.head] {
+                        let mut piece =
+                            EntityVisiblePiece::new_bitmap(bitmap, 1.0, 1.0).nudged(nudge);
+                        if flash {
+                            piece = piece.with_palette(&state.hero_flash);
                         }
+                        entity_pieces.push(piece);
                     }
-                    let new_entity = sim_region.move_entity(
+
+                    push_hit_points(&mut entity_pieces, entity);
+                    let mut new_entity = sim_region.move_entity(
                         &entity,
                         input.time_per_frame,
                         player_ddp,
                         MoveSpec::player(),
                     );
+                    new_entity.animation = animation;
+                    if new_entity.hurt > 0.0 {
+                        new_entity.hurt = (new_entity.hurt - dt).max(0.0);
+                    } else if touches_monster(&sim_region, &new_entity) && new_entity.take_hit() {
+                        debug!(host_api, "sim", "hero {:?} hurt", new_entity.idx);
+                        new_entity.hurt = HURT_TIME;
+                    }
                     new_entities.push(new_entity);
                 }
                 EntityKind::Sword => {
                     for new in &new_entities {
                         assert!(new.kind != EntityKind::Sword);
                     }
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(&state.shadow, 1.0, 1.0));
+                    entity_pieces.push(EntityVisiblePiece::new_shadow(&state.shadow));
+                    let spin = 4.0 * entity.distance_remaining;
+                    entity_pieces
+                        .push(EntityVisiblePiece::new_bitmap(&state.sword, 1.0, 1.0).rotated(spin));
                     let ddp = V2::new(0.0, 0.0);
-                    let new_entity = update_sword(&sim_region, entity, input.time_per_frame, ddp);
+                    let mut new_entity =
+                        update_sword(&sim_region, entity, input.time_per_frame, ddp);
+                    if new_entity.spatial && hits_wall(&sim_region, &new_entity) {
+                        state
+                            .particles
+                            .emit(&SPARKS, new_entity.p, new_entity.z, 24);
+                        new_entity.spatial = false;
+                        new_entity.p = WorldPosition::invalid_offset();
+                    }
                     new_entities.push(new_entity);
                 }
                 EntityKind::Wall => {
                     if debug {
                         let size = V2::new(entity.width, entity.height);
-                        let offset = 0.5 * size;
                         entity_pieces.push(EntityVisiblePiece::new_rect(
                             Color::magenta(),
                             size,
-                            offset,
+                            V2::default(),
                             1.0,
                             1.0,
                         ));
                     }
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(&state.tree, 1.0, 1.0));
+                    entity_pieces.push(
+                        EntityVisiblePiece::new_bitmap(&state.tree, 1.0, 1.0)
+                            .with_normals(&state.tree_normals),
+                    );
+                }
+                EntityKind::Prop(Prop::Tree) => {
+                    entity_pieces.push(
+                        EntityVisiblePiece::new_bitmap(&state.tree, 1.0, 1.0)
+                            .with_normals(&state.tree_normals),
+                    );
+                }
+                EntityKind::Prop(Prop::Rock) => {
+                    entity_pieces.push(EntityVisiblePiece::new_shadow(&state.shadow));
+                    entity_pieces.push(EntityVisiblePiece::new_bitmap(&state.sword, 1.0, 1.0));
                 }
                 EntityKind::Familiar => {
                     entity_pieces.push(EntityVisiblePiece::new_bitmap(
                         &hero_bitmaps.head,
-                        1.0,
+                        0.6,
                         1.0,
                     ));
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(
-                        &state.shadow,
-                        1.0,
-                        entity.t_bob,
-                    ));
+                    entity_pieces.push(EntityVisiblePiece::new_shadow(&state.shadow));
                     let new_entity = update_familiar(&sim_region, entity, input.time_per_frame);
                     new_entities.push(new_entity);
                 }
+                EntityKind::Stairs => {
+                    // each step is lifted as high as it is above the floor, and lightens as
+                    // it rises
+                    let rise = entity.climb as f32 * floor_height;
+                    let depth = (entity.height + rise.abs()) / STAIRS_STEPS as f32;
+                    for i in 0..STAIRS_STEPS {
+                        let t = (i as f32 + 0.5) / STAIRS_STEPS as f32;
+                        let z = if rise > 0.0 {
+                            t * rise
+                        } else {
+                            (1.0 - t) * rise
+                        };
+                        let shade = 0.2 + 0.4 * t;
+                        let color = Color {
+                            red: shade,
+                            green: 0.9 * shade,
+                            blue: 0.8 * shade,
+                            alpha: 1.0,
+                        };
+                        let offset = V2::new(0.0, (t - 0.5) * entity.height + z);
+                        let size = V2::new(entity.width, 0.8 * depth);
+                        entity_pieces
+                            .push(EntityVisiblePiece::new_rect(color, size, offset, 1.0, 0.0));
+                    }
+                }
+                EntityKind::Ladder => {
+                    let wood = Color {
+                        red: 0.45,
+                        green: 0.3,
+                        blue: 0.15,
+                        alpha: 1.0,
+                    };
+                    for &x in &[-0.35, 0.35] {
+                        let offset = V2::new(x * entity.width, 0.0);
+                        let size = V2::new(0.1, entity.height);
+                        entity_pieces
+                            .push(EntityVisiblePiece::new_rect(wood, size, offset, 1.0, 0.0));
+                    }
+                    for &y in &[-0.3, 0.0, 0.3] {
+                        let offset = V2::new(0.0, y * entity.height);
+                        let size = V2::new(0.7 * entity.width, 0.08);
+                        entity_pieces
+                            .push(EntityVisiblePiece::new_rect(wood, size, offset, 1.0, 0.0));
+                    }
+                }
                 EntityKind::Monster => {
-                    entity_pieces.push(EntityVisiblePiece::new_bitmap(
-                        &hero_bitmaps.torso,
-                        1.0,
-                        1.0,
-                    ));
+                    let tint = Color {
+                        red: 1.0,
+                        green: 0.4,
+                        blue: 0.4,
+                        alpha: 1.0,
+                    };
+                    entity_pieces.push(
+                        EntityVisiblePiece::new_bitmap(&hero_bitmaps.torso, 1.0, 1.0).tinted(tint),
+                    );
+                    push_hit_points(&mut entity_pieces, entity);
                 }
             }
 
             // render
-            let entity_ground_x = screen_center_x + meters_to_pixels * entity.p.x();
-            let bob_offset = 0.3 * ((1.0 - entity.t_bob) * 3.0).sin();
-            let entity_ground_y = screen_center_y - meters_to_pixels * (entity.p.y() + bob_offset);
-            let entity_ground = V2::new(entity_ground_x, entity_ground_y);
-            if entity.kind == EntityKind::Player {
-                println!("{:?}", entity_ground);
-            }
+            let floor = entity.chunk_z - sim_region.origin.abs.z;
+            let ground_z = floor as f32 * floor_height + entity.ground_z;
+            let entity_ground = camera.to_screen(entity.p) + camera.lift(ground_z);
+            let floor_alpha = floor_alpha(floor);
+            // walked on, like the ground
+            let flat = entity.kind == EntityKind::Stairs || entity.kind == EntityKind::Ladder;
             for piece in entity_pieces {
+                // shadows stay on the ground and fade as the entity rises
+                let (anchor, scale, color, z) = if piece.shadow {
+                    let fade = 1.0 / (1.0 + SHADOW_FADE_PER_METER * entity.z);
+                    let color = piece.color.with_alpha(piece.color.alpha * fade);
+                    (
+                        entity_ground,
+                        camera.scale_at(ground_z) * fade,
+                        color,
+                        ground_z,
+                    )
+                } else {
+                    let z = ground_z + entity.z;
+                    let anchor = entity_ground + camera.lift(entity.z);
+                    (anchor, camera.scale_at(z), piece.color, z)
+                };
+                let color = color.with_alpha(color.alpha * floor_alpha);
+                let key = if flat {
+                    SortKey::background()
+                } else {
+                    SortKey::new(entity.p.y(), z + piece.offset_z)
+                };
+                let key = key.on_floor(floor);
                 match piece.kind {
-                    //bitmap renders with inversed Y (hence top, not bottom)
-                    PieceKind::Bitmap(bitmap) => {
-                        if debug {
-                            state.offscreen_buffer.render_bitmap(
-                                bitmap,
-                                entity_ground - piece.offset,
-                                piece.alpha,
-                            );
-                        }
+                    PieceKind::Bitmap(bitmap)
+                        if piece.rotation != 0.0 || (scale - 1.0).abs() > std::f32::EPSILON =>
+                    {
+                        let x_axis = scale * V2::new(piece.rotation.cos(), piece.rotation.sin());
+                        let y_axis = x_axis.perp();
+                        let quad = Quad {
+                            origin: anchor - piece.offset.x() * x_axis - piece.offset.y() * y_axis,
+                            x_axis: bitmap.width as f32 * x_axis,
+                            y_axis: bitmap.height as f32 * y_axis,
+                        };
+                        render_group.push_quad(
+                            key,
+                            bitmap,
+                            piece.normals,
+                            quad,
+                            color,
+                            piece.palette,
+                        );
                     }
-                    PieceKind::Rect(color, size) => {
-                        let half = 0.5 * meters_to_pixels * size;
-                        let center = entity_ground + meters_to_pixels * piece.offset;
-                        let left_top = entity_ground - half;
-                        state.offscreen_buffer.render_rectangle(
-                            left_top,
-                            left_top + size * meters_to_pixels * 0.9,
+                    PieceKind::Bitmap(bitmap) => {
+                        render_group.push_bitmap_with(
+                            key,
+                            bitmap,
+                            piece.normals,
+                            anchor - piece.offset,
                             color,
-                        )
+                            piece.palette,
+                        );
+                    }
+                    PieceKind::Rect(size) => {
+                        // `scale` already has the zoom the camera applies
+                        let grow = scale / camera.zoom;
+                        let center = anchor + grow * camera.to_screen_offset(piece.offset);
+                        let half = 0.5 * grow * camera.meters_to_pixels() * size;
+                        render_group.push_rectangle(key, center - half, center + half, color)
                     }
                 }
             }
         }
         sim_region.update_entities(new_entities);
     }
+    let floor = sim_region.origin.abs.z;
+    state.particles.update(input.time_per_frame, |idx| {
+        let entity = sim_region
+            .entities
+            .get(&idx)
+            .filter(|e| e.spatial && e.chunk_z == floor)?;
+        Some((entity.p, entity.z))
+    });
+    state.particles.render(&mut render_group, &camera);
+    render_group.draw(&mut state.offscreen_buffer, host_api);
+
+    state.debug.draw(
+        &mut state.offscreen_buffer,
+        input,
+        &sim_region,
+        &debug_view,
+        host_api,
+    );
 
     let origin = WorldPosition::origin();
     let sim_origin = sim_region.origin;
 
     if let Some(new_camera) = sim_region.end(state.entity_focused_by_camera) {
-        state.camera = new_camera;
+        state.camera.p = new_camera;
+    }
+
+    if debug {
+        let diff = state.world.substract(origin, sim_origin);
+        state
+            .offscreen_buffer
+            .render_rectangle(diff.xy, V2::new(10.0, 10.0), Color::magenta());
     }
 
-    let diff = state.world.substract(origin, sim_origin);
     state
-        .offscreen_buffer
-        .render_rectangle(diff.xy, V2::new(10.0, 10.0), Color::magenta());
+        .capture
+        .update(input, &state.offscreen_buffer, host_api);
 
     host_api.update_canvas(
         &state.offscreen_buffer.buffer,
@@ -301,9 +785,12 @@ fn load_hero(host_api: &dyn HostApi) -> Vec<HeroBitmaps> {
     let mut result = Vec::with_capacity(4);
     for dir in ["left", "right", "front", "back"].iter() {
         let mut hero_bitmaps = HeroBitmaps {
-            head: host_api.load_bmp(&format!("assets/test/test_hero_{}_head.bmp", dir)),
-            cape: host_api.load_bmp(&format!("assets/test/test_hero_{}_cape.bmp", dir)),
-            torso: host_api.load_bmp(&format!("assets/test/test_hero_{}_torso.bmp", dir)),
+            head: load_bmp(host_api, &format!("assets/test/test_hero_{}_head.bmp", dir)),
+            cape: load_bmp(host_api, &format!("assets/test/test_hero_{}_cape.bmp", dir)),
+            torso: load_bmp(
+                host_api,
+                &format!("assets/test/test_hero_{}_torso.bmp", dir),
+            ),
         };
         hero_bitmaps.head.align_x = 72;
         hero_bitmaps.head.align_y = 182;
@@ -317,10 +804,52 @@ fn load_hero(host_api: &dyn HostApi) -> Vec<HeroBitmaps> {
     result
 }
 
+/// `HostApi::load_bmp`, with the pixels copied from the host's memory into the game's heap
+fn load_bmp(host_api: &dyn HostApi, path: &str) -> Bitmap {
+    let bitmap = host_api.load_bmp(path);
+    Bitmap {
+        pixels: bitmap.pixels.to_vec(),
+        ..bitmap
+    }
+}
+
+/// There's a single picture per facing direction, so the clips move it around
+fn hero_animations() -> Animations {
+    let up = V2::new(0.0, -3.0);
+    let down = V2::new(0.0, 2.0);
+    let idle = Clip::new(
+        Playback::Loop,
+        vec![Frame::new(0.6), Frame::new(0.6).nudged(V2::new(0.0, 1.0))],
+    );
+    let walk = Clip::new(
+        Playback::Loop,
+        vec![
+            Frame::new(0.12).with_event(AnimationEvent::Footstep),
+            Frame::new(0.12).nudged(up),
+            Frame::new(0.12).with_event(AnimationEvent::Footstep),
+            Frame::new(0.12).nudged(up),
+        ],
+    );
+    let attack = Clip::new(
+        Playback::Once,
+        vec![
+            Frame::new(0.08).nudged(down),
+            Frame::new(0.12)
+                .nudged(up)
+                .with_event(AnimationEvent::Strike),
+            Frame::new(0.1),
+        ],
+    );
+    Animations::new(idle, walk, attack)
+}
+
 fn update_sword(sim_region: &SimRegion, entity: &SimEntity, dt: f32, ddp: V2) -> SimEntity {
     let mut new_entity = sim_region.move_entity(entity, dt, ddp, MoveSpec::sword());
     let distance_traveled = (entity.p - new_entity.p).len();
     new_entity.distance_remaining -= distance_traveled;
+    // arcs through the air, landing when it runs out of distance
+    let flown = SWORD_DISTANCE - new_entity.distance_remaining;
+    new_entity.z = (SWORD_ARC * flown * new_entity.distance_remaining).max(0.0);
     if new_entity.distance_remaining < 0.0 {
         new_entity.spatial = false;
         new_entity.p = WorldPosition::invalid_offset();
@@ -328,6 +857,98 @@ fn update_sword(sim_region: &SimRegion, entity: &SimEntity, dt: f32, ddp: V2) ->
     new_entity
 }
 
+/// A row of red squares under `entity`
+fn push_hit_points<'a>(pieces: &mut ArenaStack<EntityVisiblePiece<'a>>, entity: &SimEntity) {
+    let health_dim = V2::new(0.2, 0.2);
+    let spacing_x = 1.5 * health_dim.x();
+    let mut hit_p = V2::new(
+        -0.5 * (entity.hit_points.len() as f32 - 1.0) * spacing_x,
+        -0.25,
+    );
+    let dhit_p = V2::new(spacing_x, 0.0);
+    for hit_point in &entity.hit_points {
+        let color = if hit_point.filled() > 0 {
+            Color::red()
+        } else {
+            Color::black()
+        };
+        pieces.push(EntityVisiblePiece::new_rect(
+            color, health_dim, hit_p, 1.0, 1.0,
+        ));
+        hit_p += dhit_p;
+    }
+}
+
+/// `GAME_SEED` if set, so a dungeon can be replayed, or a new one every time
+fn dungeon_seed() -> u32 {
+    if let Some(seed) = std::env::var("GAME_SEED").ok().and_then(|s| s.parse().ok()) {
+        return seed;
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    now.map_or(0, |d| d.as_nanos() as u32)
+}
+
+/// The level file `GAME_LEVEL` names, read again on every restart so it can be edited while
+/// the game runs: a Tiled map if it ends with `.json`. A broken one is reported and left out
+fn load_level(host_api: &dyn HostApi) -> Option<World> {
+    let path = std::env::var("GAME_LEVEL").ok()?;
+    let text = match host_api.read_file(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            error!(host_api, "world", "failed to read {}: {}", path, e);
+            return None;
+        }
+    };
+    let world = if path.ends_with(".json") {
+        tiled::load(&text)
+    } else {
+        level::load(&text).map_err(|e| e.to_string())
+    };
+    match world {
+        Ok(world) => {
+            info!(host_api, "world", "loaded {}", path);
+            Some(world)
+        }
+        Err(e) => {
+            error!(host_api, "world", "{}:{}", path, e);
+            None
+        }
+    }
+}
+
+/// Whether `entity` is inside of a wall of its floor
+fn hits_wall(sim_region: &SimRegion, entity: &SimEntity) -> bool {
+    sim_region.entities.values().any(|e| {
+        let bounds = Rect2::new_center_dim(e.p, V2::new(e.width, e.height));
+        e.kind == EntityKind::Wall && e.chunk_z == entity.chunk_z && bounds.contains(entity.p)
+    })
+}
+
+/// Whether a monster of `entity`'s floor is within `TOUCH_DISTANCE` of it
+fn touches_monster(sim_region: &SimRegion, entity: &SimEntity) -> bool {
+    sim_region.entities.values().any(|e| {
+        let reach = V2::new(
+            0.5 * (e.width + entity.width) + TOUCH_DISTANCE,
+            0.5 * (e.height + entity.height) + TOUCH_DISTANCE,
+        );
+        let d = e.p - entity.p;
+        e.kind == EntityKind::Monster
+            && e.spatial
+            && e.chunk_z == entity.chunk_z
+            && d.x().abs() <= reach.x()
+            && d.y().abs() <= reach.y()
+    })
+}
+
+/// Of the entities `floor` floors above the camera's
+fn floor_alpha(floor: i32) -> f32 {
+    match floor.cmp(&0) {
+        std::cmp::Ordering::Greater => FLOOR_ABOVE_ALPHA,
+        std::cmp::Ordering::Less => FLOOR_BELOW_ALPHA,
+        std::cmp::Ordering::Equal => 1.0,
+    }
+}
+
 fn update_familiar(sim_region: &SimRegion, entity: &SimEntity, dt: f32) -> SimEntity {
     let mut entity = entity.clone();
     let closest_distance = 10.0f32.powi(2);
@@ -337,9 +958,10 @@ fn update_familiar(sim_region: &SimRegion, entity: &SimEntity, dt: f32) -> SimEn
     if entity.t_bob >= (2.0 * std::f32::consts::PI) {
         entity.t_bob -= 2.0 * std::f32::consts::PI;
     }
+    entity.z = FAMILIAR_HOVER + 0.3 * (3.0 * entity.t_bob).sin();
 
-    for (_, other) in &sim_region.entities {
-        if other.kind == EntityKind::Player {
+    for other in sim_region.entities.values() {
+        if other.kind == EntityKind::Player && other.chunk_z == entity.chunk_z {
             let distance = (other.p - entity.p).len_sq();
             if distance < closest_distance && distance > 2.0 {
                 closest_hero = Some(other.p);
@@ -348,6 +970,7 @@ fn update_familiar(sim_region: &SimRegion, entity: &SimEntity, dt: f32) -> SimEn
     }
 
     if let Some(hero_p) = closest_hero {
+        debug_draw::line(entity.p, hero_p, Color::yellow());
         let acc = 0.5;
         let one_over_length = acc / closest_distance.sqrt();
         let ddp = one_over_length * (hero_p - entity.p);
@@ -357,40 +980,99 @@ fn update_familiar(sim_region: &SimRegion, entity: &SimEntity, dt: f32) -> SimEn
     }
 }
 
+/// Lives in the host's permanent memory block along with everything it owns, allocated from
+/// `heap`: copying the block snapshots the whole game
 #[repr(C)]
 pub struct GameState {
     pub offscreen_buffer: OffscreenBuffer,
     pub world: World,
-    pub camera: WorldPosition,
+    pub camera: Camera,
     entity_focused_by_camera: Option<StorageIdx>,
 
     storage: Storage,
 
-    backdrop: Bitmap,
+    ground: GroundCache,
+    ground_art: GroundArt,
     shadow: Bitmap,
     tree: Bitmap,
+    tree_normals: Bitmap,
+    sword: Bitmap,
     hero_bitmaps: Vec<HeroBitmaps>,
+    /// Turns the hero's pieces white while it is hurt
+    hero_flash: Palette,
+    hero_animations: Animations,
+    particles: ParticleSystem,
+
+    debug: DebugState,
+    editor: Editor,
+    capture: Capture,
+
+    /// At the start of the permanent block, every entry point hands it to `GameAllocator`
+    heap: &'static Heap,
+    /// Per-frame allocations, reset at the start of `game_update`
+    transient: Arena,
 }
 
 impl GameState {
-    fn start(&mut self) {
-        self.world = World::new();
-        self.camera = self.world.initial_camera();
+    fn start(&mut self, host_api: &dyn HostApi) {
+        let world = match load_level(host_api) {
+            Some(world) => world,
+            None => {
+                let seed = dungeon_seed();
+                info!(
+                    host_api,
+                    "world", "dungeon seed {} (set GAME_SEED to replay it)", seed
+                );
+                World::generate(seed)
+            }
+        };
+        self.start_on(world, host_api);
+    }
+
+    /// Spawns everything `world` holds, the camera on the player
+    fn start_on(&mut self, world: World, host_api: &dyn HostApi) {
+        self.world = world;
+        self.camera.p = self.world.initial_player();
         self.storage = Storage::default();
+        self.ground.clear();
+        self.particles.clear(self.camera.p);
+        for light in &self.world.lights {
+            let anchor = EmitterAnchor::World(light.p);
+            let emitter = Emitter::new(anchor, light.z, 12.0, &EMBERS);
+            self.particles.add_emitter(emitter);
+        }
         self.entity_focused_by_camera = None;
-        let idx = self.add_player(self.world.initial_player());
-        self.entity_focused_by_camera = Some(idx);
-        self.add_monster(self.world.initial_monster());
-        self.add_familiars();
+        self.add_spawns();
         self.add_walls();
+        self.add_stairs();
+        self.world.debug_stuff(host_api);
     }
 
     fn tile_side(&self) -> f32 {
         self.world.tile_side
     }
 
-    fn add_familiars(&mut self) {
-        self.add_familiar(self.world.initial_camera());
+    /// The camera follows the first player
+    fn add_spawns(&mut self) {
+        for spawn in self.world.spawns.clone() {
+            let (x, y, z) = spawn.tile();
+            let p = self.world.position_at_tile(x, y, z);
+            match spawn {
+                Spawn::Player { hit_points, .. } => {
+                    let idx = self.add_player(p, hit_points);
+                    self.entity_focused_by_camera = self.entity_focused_by_camera.or(Some(idx));
+                }
+                Spawn::Monster { hit_points, .. } => {
+                    self.add_monster(p, hit_points);
+                }
+                Spawn::Familiar { .. } => {
+                    self.add_familiar(p);
+                }
+                Spawn::Prop { prop, .. } => {
+                    self.add_prop(p, prop);
+                }
+            }
+        }
     }
 
     fn add_walls(&mut self) {
@@ -401,6 +1083,29 @@ impl GameState {
         }
     }
 
+    fn add_stairs(&mut self) {
+        for stairs in self.world.stairs.clone() {
+            let (x, y, z) = stairs.tile;
+            let first = self.world.position_at_tile(x, y, z);
+            // centered between its first and last tile
+            let along = 0.5 * (stairs.length - 1) as f32 * self.tile_side();
+            let p = self.world.map_into_chunk_space(first, V2::new(0.0, along));
+            let kind = match stairs.kind {
+                StairsKind::Stairs => EntityKind::Stairs,
+                StairsKind::Ladder => EntityKind::Ladder,
+            };
+            let entity = SimEntity {
+                kind,
+                width: self.tile_side(),
+                height: stairs.length as f32 * self.tile_side(),
+                spatial: true,
+                climb: stairs.climb,
+                ..Default::default()
+            };
+            self.add_low(p, entity);
+        }
+    }
+
     fn add_low(&mut self, p: WorldPosition, entity: SimEntity) -> StorageIdx {
         //TODO push & update pos should be combined once world contains storage
         let low_entity_idx = self.storage.len();
@@ -409,7 +1114,6 @@ impl GameState {
             p: WorldPosition::default(),
         };
         self.world.change_entity_chunks(low_entity_idx, &mut low, p);
-        self.world.debug_stuff();
         self.storage.push(low)
     }
 
@@ -425,13 +1129,27 @@ impl GameState {
         self.add_low(p, entity)
     }
 
-    fn add_monster(&mut self, p: WorldPosition) -> StorageIdx {
+    /// Narrower than a tile, to walk around
+    fn add_prop(&mut self, p: WorldPosition, prop: Prop) -> StorageIdx {
+        let entity = SimEntity {
+            kind: EntityKind::Prop(prop),
+            width: 0.5 * self.tile_side(),
+            height: 0.5 * self.tile_side(),
+            collides: true,
+            spatial: true,
+            ..Default::default()
+        };
+        self.add_low(p, entity)
+    }
+
+    fn add_monster(&mut self, p: WorldPosition, hit_points: usize) -> StorageIdx {
         let entity = SimEntity {
             kind: EntityKind::Monster,
             width: 1.0,
             height: 0.5,
             collides: true,
             spatial: true,
+            hit_points: vec![HitPoint::full(); hit_points],
             ..Default::default()
         };
         self.add_low(p, entity)
@@ -445,17 +1163,20 @@ impl GameState {
             spatial: true,
             ..Default::default()
         };
-        self.add_low(p, entity)
+        let idx = self.add_low(p, entity);
+        let emitter = Emitter::new(EmitterAnchor::Entity(idx), 0.0, 20.0, &MAGIC_TRAIL);
+        self.particles.add_emitter(emitter);
+        idx
     }
 
-    fn add_player(&mut self, p: WorldPosition) -> StorageIdx {
+    fn add_player(&mut self, p: WorldPosition, hit_points: usize) -> StorageIdx {
         let entity = SimEntity {
             kind: EntityKind::Player,
             width: 1.0,
             height: 0.5,
             collides: true,
             spatial: true,
-            hit_points: vec![HitPoint::full(); 3],
+            hit_points: vec![HitPoint::full(); hit_points],
             sword: Some(self.add_sword()),
             ..Default::default()
         };
@@ -481,33 +1202,85 @@ pub struct HeroBitmaps {
 
 pub struct EntityVisiblePiece<'a> {
     kind: PieceKind<'a>,
+    /// Bitmaps: from the top-left corner to the entity's ground point, in bitmap pixels.
+    /// Rects: from the entity's ground point to the center, in meters
     offset: V2,
-    _offset_z: f32,
-    alpha: f32,
+    /// Pieces of entities at the same `y` are drawn from low to high `offset_z`
+    offset_z: f32,
+    /// Fill color of rects, tint of bitmaps
+    color: Color,
+    normals: Option<&'a Bitmap>,
+    palette: Option<&'a Palette>,
+    /// Clockwise on screen, in radians, around `offset`
+    rotation: f32,
+    /// Stays on the ground, shrinking and fading as the entity rises
+    shadow: bool,
 }
 
 enum PieceKind<'a> {
     Bitmap(&'a Bitmap),
-    Rect(Color, V2),
+    Rect(V2),
 }
 
 impl<'a> EntityVisiblePiece<'a> {
-    fn new_rect(color: Color, size: V2, offset: V2, alpha: f32, _offset_z: f32) -> Self {
+    fn new_rect(color: Color, size: V2, offset: V2, alpha: f32, offset_z: f32) -> Self {
         Self {
-            kind: PieceKind::Rect(color, size),
+            kind: PieceKind::Rect(size),
             offset,
-            _offset_z,
-            alpha,
+            offset_z,
+            color: color.with_alpha(alpha),
+            normals: None,
+            palette: None,
+            rotation: 0.0,
+            shadow: false,
         }
     }
 
-    fn new_bitmap(bitmap: &'a Bitmap, alpha: f32, _offset_z: f32) -> Self {
+    fn new_bitmap(bitmap: &'a Bitmap, alpha: f32, offset_z: f32) -> Self {
         let offset = V2::new(bitmap.align_x as f32, bitmap.align_y as f32);
         Self {
             kind: PieceKind::Bitmap(bitmap),
             offset,
-            _offset_z,
-            alpha,
+            offset_z,
+            color: Color::white().with_alpha(alpha),
+            normals: None,
+            palette: None,
+            rotation: 0.0,
+            shadow: false,
+        }
+    }
+
+    fn rotated(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    fn new_shadow(bitmap: &'a Bitmap) -> Self {
+        Self {
+            shadow: true,
+            ..Self::new_bitmap(bitmap, 1.0, 0.0)
         }
     }
+
+    /// Moves the piece by `d` bitmap pixels
+    fn nudged(mut self, d: V2) -> Self {
+        self.offset = self.offset - d;
+        self
+    }
+
+    fn with_normals(mut self, normals: &'a Bitmap) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    fn with_palette(mut self, palette: &'a Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Keeps the piece's alpha
+    fn tinted(mut self, tint: Color) -> Self {
+        self.color = tint.with_alpha(self.color.alpha * tint.alpha);
+        self
+    }
 }
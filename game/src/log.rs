@@ -0,0 +1,48 @@
+//! Leveled, tagged logging through `HostApi::log`.
+//! The message is only formatted if the host keeps it:
+//! `debug!(host_api, "world", "chunk count: {}", count)`
+
+macro_rules! log {
+    ($host_api:expr, $level:expr, $tag:expr, $($arg:tt)+) => {{
+        let level = $level;
+        let tag = $tag;
+        if $host_api.log_enabled(level, tag) {
+            $host_api.log(level, tag, &format!($($arg)+));
+        }
+    }};
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+    ($host_api:expr, $tag:expr, $($arg:tt)+) => {
+        log!($host_api, crate::host_api::LogLevel::Error, $tag, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($host_api:expr, $tag:expr, $($arg:tt)+) => {
+        log!($host_api, crate::host_api::LogLevel::Warn, $tag, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! info {
+    ($host_api:expr, $tag:expr, $($arg:tt)+) => {
+        log!($host_api, crate::host_api::LogLevel::Info, $tag, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($host_api:expr, $tag:expr, $($arg:tt)+) => {
+        log!($host_api, crate::host_api::LogLevel::Debug, $tag, $($arg)+)
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($host_api:expr, $tag:expr, $($arg:tt)+) => {
+        log!($host_api, crate::host_api::LogLevel::Trace, $tag, $($arg)+)
+    };
+}
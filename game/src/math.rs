@@ -1,5 +1,7 @@
+mod random;
 mod rectangle;
 mod v2;
 
+pub use random::*;
 pub use rectangle::*;
 pub use v2::*;
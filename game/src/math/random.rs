@@ -0,0 +1,45 @@
+/// Xorshift, small and deterministic: the same seed always yields the same numbers
+#[derive(Copy, Clone, Debug)]
+pub struct Random(u32);
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck on 0
+        Self(seed | 1)
+    }
+
+    /// Seeded from a point of an integer grid, so neighbors get unrelated numbers
+    pub fn at(x: i32, y: i32, z: i32) -> Self {
+        Self::new(
+            (x as u32).wrapping_mul(73_856_093)
+                ^ (y as u32).wrapping_mul(19_349_663)
+                ^ (z as u32).wrapping_mul(83_492_791),
+        )
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// In [0, 1)
+    pub fn unilateral(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// In [-1, 1)
+    pub fn bilateral(&mut self) -> f32 {
+        2.0 * self.unilateral() - 1.0
+    }
+
+    /// In [min, max)
+    pub fn between(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.unilateral()
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        self.next_u32() as usize % n
+    }
+}
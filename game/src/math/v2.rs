@@ -7,7 +7,7 @@ pub struct V2 {
 }
 
 impl V2 {
-    pub fn new(x: f32, y: f32) -> Self {
+    pub const fn new(x: f32, y: f32) -> Self {
         Self { x, y }
     }
 
@@ -39,6 +39,14 @@ impl V2 {
         Self::inner(self, self)
     }
 
+    /// Rotated a quarter turn, from the x axis towards the y axis
+    pub fn perp(self) -> Self {
+        Self {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+
     pub fn rev(self) -> Self {
         Self {
             x: self.y,
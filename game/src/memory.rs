@@ -0,0 +1,381 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::mem::{self, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// Alignment and granularity of `Heap` allocations, room for a `FreeRegion`
+const HEAP_ALIGN: usize = 16;
+
+/// First fit allocator over a block of host memory (see `GameMemory`), behind every allocation
+/// of the game through `GameAllocator`.
+///
+/// It sits at the start of its own block and links the free regions through their first
+/// bytes, in address order: copying the block copies the heap and everything allocated from
+/// it, which is valid again once copied back to the same address
+pub struct Heap {
+    locked: AtomicBool,
+    free: UnsafeCell<*mut FreeRegion>,
+    start: usize,
+    end: usize,
+}
+
+struct FreeRegion {
+    size: usize,
+    next: *mut FreeRegion,
+}
+
+// every access to `free` holds `locked`
+unsafe impl Sync for Heap {
+}
+
+impl Heap {
+    /// # Safety
+    /// `block` must outlive every use of the heap and must not be accessed through anything else
+    pub unsafe fn new(block: &mut [u8]) -> &'static Heap {
+        assert!(mem::size_of::<FreeRegion>() <= HEAP_ALIGN);
+        let base = block.as_mut_ptr() as usize;
+        let heap = align_up(base, mem::align_of::<Heap>());
+        let start = align_up(heap + mem::size_of::<Heap>(), HEAP_ALIGN);
+        let end = (base + block.len()) & !(HEAP_ALIGN - 1);
+        assert!(start < end, "heap block of {} bytes too small", block.len());
+
+        let region = start as *mut FreeRegion;
+        region.write(FreeRegion {
+            size: end - start,
+            next: ptr::null_mut(),
+        });
+        let heap = heap as *mut Heap;
+        heap.write(Heap {
+            locked: AtomicBool::new(false),
+            free: UnsafeCell::new(region),
+            start,
+            end,
+        });
+        &*heap
+    }
+
+    /// Whether `p` was allocated from this heap
+    pub fn contains(&self, p: *mut u8) -> bool {
+        (self.start..self.end).contains(&(p as usize))
+    }
+
+    /// Null when no free region fits
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = heap_size(layout);
+        let align = layout.align().max(HEAP_ALIGN);
+        let _lock = self.lock();
+        unsafe {
+            let mut link = self.free.get();
+            while !(*link).is_null() {
+                let region = *link;
+                let region_start = region as usize;
+                let region_end = region_start + (*region).size;
+                let start = align_up(region_start, align);
+                if start + size <= region_end {
+                    // what the allocation leaves before and after itself stays free
+                    let next = (*region).next;
+                    let after = if start + size < region_end {
+                        let tail = (start + size) as *mut FreeRegion;
+                        tail.write(FreeRegion {
+                            size: region_end - (start + size),
+                            next,
+                        });
+                        tail
+                    } else {
+                        next
+                    };
+                    if start > region_start {
+                        (*region).size = start - region_start;
+                        (*region).next = after;
+                    } else {
+                        *link = after;
+                    }
+                    return start as *mut u8;
+                }
+                link = &mut (*region).next;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// # Safety
+    /// `p` must come from `alloc` on this heap with the same `layout`, and not be freed yet
+    pub unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        let size = heap_size(layout);
+        let start = p as usize;
+        let _lock = self.lock();
+        let mut prev: *mut FreeRegion = ptr::null_mut();
+        let mut next = *self.free.get();
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let region = p as *mut FreeRegion;
+        region.write(FreeRegion { size, next });
+        // merged with the free regions it touches
+        if !next.is_null() && start + size == next as usize {
+            (*region).size += (*next).size;
+            (*region).next = (*next).next;
+        }
+        if prev.is_null() {
+            *self.free.get() = region;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*region).size;
+            (*prev).next = (*region).next;
+        } else {
+            (*prev).next = region;
+        }
+    }
+
+    fn lock(&self) -> HeapLock<'_> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::sync::atomic::spin_loop_hint();
+        }
+        HeapLock(&self.locked)
+    }
+}
+
+struct HeapLock<'a>(&'a AtomicBool);
+
+impl<'a> Drop for HeapLock<'a> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+fn heap_size(layout: Layout) -> usize {
+    align_up(layout.size().max(1), HEAP_ALIGN)
+}
+
+fn align_up(x: usize, align: usize) -> usize {
+    (x + align - 1) & !(align - 1)
+}
+
+/// The heap `GameAllocator` allocates from, none until `use_heap`
+static HEAP: AtomicPtr<Heap> = AtomicPtr::new(ptr::null_mut());
+
+/// Sends every allocation of the game to `heap`. A reloaded library starts without one, so
+/// every entry point sets it again
+pub fn use_heap(heap: &'static Heap) {
+    HEAP.store(heap as *const Heap as *mut Heap, Ordering::Release);
+}
+
+/// Global allocator of the game library: allocates from the heap of `use_heap`, so the game
+/// state and everything it owns end up in the host's permanent block. Before that, and for
+/// memory the host allocated, it falls back to the system allocator
+pub struct GameAllocator;
+
+unsafe impl GlobalAlloc for GameAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match HEAP.load(Ordering::Acquire).as_ref() {
+            Some(heap) => heap.alloc(layout),
+            None => System.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        match HEAP.load(Ordering::Acquire).as_ref() {
+            Some(heap) if heap.contains(p) => heap.dealloc(p, layout),
+            _ => System.dealloc(p, layout),
+        }
+    }
+}
+
+/// Bump allocator over a block of host memory (see `GameMemory`).
+///
+/// Allocations only go away all at once, on `reset`. `ArenaStack` drops its own elements
+pub struct Arena {
+    base: *mut u8,
+    size: usize,
+    used: Cell<usize>,
+}
+
+impl Arena {
+    /// # Safety
+    /// `block` must outlive the arena and must not be accessed through anything else
+    pub unsafe fn new(block: &mut [u8]) -> Self {
+        Self {
+            base: block.as_mut_ptr(),
+            size: block.len(),
+            used: Cell::new(0),
+        }
+    }
+
+    /// Frees everything at once. Taking `&mut self` ensures no allocation is still borrowed
+    pub fn reset(&mut self) {
+        self.used.set(0);
+    }
+
+    fn push_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        let start = self.base as usize + self.used.get();
+        let padding = (align - start % align) % align;
+        let new_used = self.used.get() + padding + size;
+        assert!(
+            new_used <= self.size,
+            "arena out of memory: {} of {} bytes",
+            new_used,
+            self.size
+        );
+        self.used.set(new_used);
+        (start + padding) as *mut u8
+    }
+
+    /// Fixed capacity stack, panics when pushing past `capacity`
+    pub fn stack<T>(&self, capacity: usize) -> ArenaStack<'_, T> {
+        let p = self.push_bytes(
+            mem::size_of::<T>() * capacity,
+            mem::align_of::<MaybeUninit<T>>(),
+        ) as *mut MaybeUninit<T>;
+        let items = unsafe { std::slice::from_raw_parts_mut(p, capacity) };
+        ArenaStack { items, len: 0 }
+    }
+}
+
+pub struct ArenaStack<'a, T> {
+    items: &'a mut [MaybeUninit<T>],
+    len: usize,
+}
+
+impl<'a, T> ArenaStack<'a, T> {
+    pub fn push(&mut self, value: T) {
+        assert!(
+            self.len < self.items.len(),
+            "arena stack full: {} items",
+            self.len
+        );
+        self.items[self.len] = MaybeUninit::new(value);
+        self.len += 1;
+    }
+}
+
+impl<'a, T> Deref for ArenaStack<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.items.as_ptr() as *const T, self.len) }
+    }
+}
+
+impl<'a, T> DerefMut for ArenaStack<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.items.as_mut_ptr() as *mut T, self.len) }
+    }
+}
+
+impl<'a, T> Drop for ArenaStack<'a, T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.deref_mut()) }
+    }
+}
+
+impl<'b, 'a, T> IntoIterator for &'b ArenaStack<'a, T> {
+    type IntoIter = std::slice::Iter<'b, T>;
+    type Item = &'b T;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for ArenaStack<'a, T> {
+    type IntoIter = ArenaStackIter<'a, T>;
+    type Item = T;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        // the iterator takes over the elements, `self` must not drop them
+        let len = mem::replace(&mut self.len, 0);
+        let items = mem::replace(&mut self.items, &mut []);
+        ArenaStackIter {
+            items,
+            next: 0,
+            len,
+        }
+    }
+}
+
+pub struct ArenaStackIter<'a, T> {
+    items: &'a mut [MaybeUninit<T>],
+    next: usize,
+    len: usize,
+}
+
+impl<'a, T> Iterator for ArenaStackIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.next < self.len {
+            let item = unsafe { ptr::read(self.items[self.next].as_ptr()) };
+            self.next += 1;
+            Some(item)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T> Drop for ArenaStackIter<'a, T> {
+    fn drop(&mut self) {
+        for item in &mut self.items[self.next..self.len] {
+            unsafe { ptr::drop_in_place(item.as_mut_ptr()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_reuses_and_merges_freed_regions() {
+        let mut block = vec![0u8; 4096];
+        let heap = unsafe { Heap::new(&mut block) };
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let a = heap.alloc(small);
+        let b = heap.alloc(small);
+        let c = heap.alloc(small);
+        assert!(heap.contains(a) && heap.contains(c));
+        assert_eq!(b as usize - a as usize, 32);
+        let aligned = heap.alloc(Layout::from_size_align(8, 256).unwrap());
+        assert_eq!(aligned as usize % 256, 0);
+
+        unsafe {
+            heap.dealloc(b, small);
+            heap.dealloc(a, small);
+        }
+        // a and b merged back into one region
+        let ab = heap.alloc(Layout::from_size_align(64, 8).unwrap());
+        assert_eq!(ab, a);
+        assert!(heap
+            .alloc(Layout::from_size_align(8192, 8).unwrap())
+            .is_null());
+    }
+
+    #[test]
+    fn copying_the_block_snapshots_the_heap() {
+        let mut block = vec![0u8; 4096];
+        let base = block.as_mut_ptr();
+        let heap = unsafe { Heap::new(&mut block) };
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let kept = heap.alloc(layout);
+        unsafe { *kept = 7 };
+        let snapshot = unsafe { std::slice::from_raw_parts(base, 4096) }.to_vec();
+
+        let later = heap.alloc(layout);
+        unsafe {
+            *kept = 9;
+            heap.dealloc(kept, layout);
+            ptr::copy_nonoverlapping(snapshot.as_ptr(), base, snapshot.len());
+        }
+
+        assert_eq!(unsafe { *kept }, 7);
+        // `later` is free again, `kept` isn't
+        assert_eq!(heap.alloc(layout), later);
+    }
+}
@@ -0,0 +1,308 @@
+use super::*;
+
+/// Live particles are dropped past this, new ones are ignored
+pub const MAX_PARTICLES: usize = 1024;
+
+/// Piecewise linear over a particle's life: `(t, value)` keys with `t` going from 0 (born) to
+/// 1 (dead), in increasing order
+pub type Curve = &'static [(f32, f32)];
+pub type ColorCurve = &'static [(f32, Color)];
+
+#[derive(Copy, Clone, Debug)]
+pub enum Appearance {
+    /// One of the system's bitmaps, `size` meters wide
+    Bitmap { index: usize, size: f32 },
+    /// In meters
+    Rect(V2),
+}
+
+/// How particles of one kind are born and age. Ranges are `(min, max)`
+#[derive(Copy, Clone, Debug)]
+pub struct ParticleSpec {
+    /// In seconds
+    pub lifetime: (f32, f32),
+    /// Counterclockwise from the x axis, in radians
+    pub direction: f32,
+    /// How far off `direction` particles can head, either way
+    pub spread: f32,
+    /// In meters per second
+    pub speed: (f32, f32),
+    pub dz: (f32, f32),
+    pub ddp: V2,
+    /// Gravity is negative
+    pub ddz: f32,
+    /// Fraction of the velocity lost per second
+    pub drag: f32,
+    pub color: ColorCurve,
+    /// Multiplies the alpha of `color`
+    pub alpha: Curve,
+    pub appearance: Appearance,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    p: V2,
+    z: f32,
+    dp: V2,
+    dz: f32,
+    t: f32,
+    lifetime: f32,
+    spec: &'static ParticleSpec,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum EmitterAnchor {
+    Entity(StorageIdx),
+    World(WorldPosition),
+}
+
+/// Spawns `rate` particles per second at its anchor, `z` meters above the ground
+#[derive(Copy, Clone, Debug)]
+pub struct Emitter {
+    pub anchor: EmitterAnchor,
+    pub z: f32,
+    pub rate: f32,
+    pub spec: &'static ParticleSpec,
+    /// Particles owed since the last spawn
+    debt: f32,
+    /// Sim space position of `EmitterAnchor::World`, refreshed by `recenter`. `None` while
+    /// it is on another floor or out of the sim region
+    p: Option<V2>,
+}
+
+impl Emitter {
+    pub fn new(anchor: EmitterAnchor, z: f32, rate: f32, spec: &'static ParticleSpec) -> Self {
+        Self {
+            anchor,
+            z,
+            rate,
+            spec,
+            debt: 0.0,
+            p: None,
+        }
+    }
+}
+
+/// Particles live in sim space around `origin`, which follows the camera so they can be
+/// emitted at, and drawn like, sim entities
+pub struct ParticleSystem {
+    origin: WorldPosition,
+    particles: Vec<Particle>,
+    emitters: Vec<Emitter>,
+    bitmaps: Vec<Bitmap>,
+    random: Random,
+}
+
+impl ParticleSystem {
+    pub fn new(bitmaps: Vec<Bitmap>) -> Self {
+        Self {
+            origin: WorldPosition::origin(),
+            particles: Vec::with_capacity(MAX_PARTICLES),
+            emitters: vec![],
+            bitmaps,
+            random: Random::new(0x5EED),
+        }
+    }
+
+    /// Drops every particle and emitter
+    pub fn clear(&mut self, origin: WorldPosition) {
+        self.origin = origin;
+        self.particles.clear();
+        self.emitters.clear();
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) {
+        self.emitters.push(emitter);
+    }
+
+    /// Moves sim space to be centered on `origin`. World anchored emitters only run on the
+    /// origin's floor and inside `bounds`, like the entities the sim region updates
+    pub fn recenter(&mut self, world: &World, origin: WorldPosition, bounds: Rect2) {
+        let shift = world.substract(self.origin, origin).xy;
+        for particle in &mut self.particles {
+            particle.p += shift;
+        }
+        self.origin = origin;
+        for emitter in &mut self.emitters {
+            if let EmitterAnchor::World(p) = emitter.anchor {
+                let diff = world.substract(p, origin);
+                emitter.p = if diff.z == 0.0 && bounds.contains(diff.xy) {
+                    Some(diff.xy)
+                } else {
+                    None
+                };
+            }
+        }
+    }
+
+    /// Spawns `count` particles at `p`, `z` meters above the ground
+    pub fn emit(&mut self, spec: &'static ParticleSpec, p: V2, z: f32, count: usize) {
+        for _ in 0..count {
+            if self.particles.len() == MAX_PARTICLES {
+                return;
+            }
+            let random = &mut self.random;
+            let angle = spec.direction + spec.spread * random.bilateral();
+            let speed = random.between(spec.speed.0, spec.speed.1);
+            self.particles.push(Particle {
+                p,
+                z,
+                dp: speed * V2::new(angle.cos(), angle.sin()),
+                dz: random.between(spec.dz.0, spec.dz.1),
+                t: 0.0,
+                lifetime: random.between(spec.lifetime.0, spec.lifetime.1),
+                spec,
+            });
+        }
+    }
+
+    /// Runs the emitters, then ages and moves every particle. `entity_at` tells where the
+    /// entities emitters are attached to stand, if they are being simulated
+    pub fn update(&mut self, dt: f32, entity_at: impl Fn(StorageIdx) -> Option<(V2, f32)>) {
+        for i in 0..self.emitters.len() {
+            let emitter = self.emitters[i];
+            let at = match emitter.anchor {
+                EmitterAnchor::Entity(idx) => entity_at(idx),
+                EmitterAnchor::World(_) => emitter.p.map(|p| (p, 0.0)),
+            };
+            let debt = emitter.debt + emitter.rate * dt;
+            let count = debt.floor();
+            self.emitters[i].debt = debt - count;
+            if let Some((p, z)) = at {
+                self.emit(emitter.spec, p, z + emitter.z, count as usize);
+            }
+        }
+
+        for particle in &mut self.particles {
+            let spec = particle.spec;
+            particle.t += dt;
+            let drag = (1.0 - spec.drag * dt).max(0.0);
+            particle.p += dt * particle.dp + 0.5 * dt * dt * spec.ddp;
+            particle.dp = drag * (particle.dp + dt * spec.ddp);
+            particle.z += dt * particle.dz + 0.5 * dt * dt * spec.ddz;
+            particle.dz = drag * (particle.dz + dt * spec.ddz);
+            // they don't go through the floor
+            if particle.z < 0.0 {
+                particle.z = 0.0;
+                particle.dz = 0.0;
+            }
+        }
+        self.particles.retain(|p| p.t < p.lifetime);
+    }
+
+    pub fn render<'a>(&'a self, render_group: &mut RenderGroup<'a>, camera: &Camera) {
+        for particle in &self.particles {
+            let age = particle.t / particle.lifetime;
+            let color = sample_color(particle.spec.color, age);
+            let color = color.with_alpha(color.alpha * sample(particle.spec.alpha, age));
+            let key = SortKey::new(particle.p.y(), particle.z);
+            let center = camera.to_screen(particle.p) + camera.lift(particle.z);
+            match particle.spec.appearance {
+                Appearance::Bitmap { index, size } => {
+                    let bitmap = &self.bitmaps[index];
+                    let width = size * camera.meters_to_pixels();
+                    let x_axis = V2::new(width, 0.0);
+                    let y_axis = V2::new(0.0, width * bitmap.height as f32 / bitmap.width as f32);
+                    let quad = Quad {
+                        origin: center - 0.5 * (x_axis + y_axis),
+                        x_axis,
+                        y_axis,
+                    };
+                    render_group.push_quad(key, bitmap, None, quad, color, None);
+                }
+                Appearance::Rect(size) => {
+                    let half = 0.5 * camera.meters_to_pixels() * size;
+                    render_group.push_rectangle(key, center - half, center + half, color);
+                }
+            }
+        }
+    }
+}
+
+fn sample(curve: Curve, t: f32) -> f32 {
+    sample_with(curve, t, |a, b, t| a + (b - a) * t)
+}
+
+fn sample_color(curve: ColorCurve, t: f32) -> Color {
+    sample_with(curve, t, |a, b, t| {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Color {
+            red: lerp(a.red, b.red),
+            green: lerp(a.green, b.green),
+            blue: lerp(a.blue, b.blue),
+            alpha: lerp(a.alpha, b.alpha),
+        }
+    })
+}
+
+fn sample_with<T: Copy>(curve: &[(f32, T)], t: f32, lerp: impl Fn(T, T, f32) -> T) -> T {
+    let (first_t, first) = curve[0];
+    if t <= first_t {
+        return first;
+    }
+    for pair in curve.windows(2) {
+        let ((t0, a), (t1, b)) = (pair[0], pair[1]);
+        if t < t1 {
+            return lerp(a, b, (t - t0) / (t1 - t0));
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+/// White disc fading out towards its edge, `side` pixels across
+pub fn soft_dot(side: usize) -> Bitmap {
+    let mut pixels = Vec::with_capacity(side * side * 4);
+    let radius = 0.5 * side as f32;
+    for y in 0..side {
+        for x in 0..side {
+            let d = V2::new(x as f32 + 0.5 - radius, y as f32 + 0.5 - radius).len() / radius;
+            let alpha = (1.0 - d).max(0.0).powi(2);
+            // premultiplied like every loaded bitmap
+            let color = (255.0 * alpha.sqrt()).round() as u8;
+            let alpha = (255.0 * alpha).round() as u8;
+            pixels.extend_from_slice(&[color, color, color, alpha]);
+        }
+    }
+    Bitmap {
+        align_x: 0,
+        align_y: 0,
+        width: side,
+        height: side,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_interpolate_between_keys() {
+        let curve: Curve = &[(0.0, 0.0), (0.25, 1.0), (1.0, 0.5)];
+        let samples: Vec<f32> = [-1.0, 0.125, 0.25, 0.625, 2.0]
+            .iter()
+            .map(|&t| sample(curve, t))
+            .collect();
+        let expected = [0.0, 0.5, 1.0, 0.75, 0.5];
+        let close = samples
+            .iter()
+            .zip(&expected)
+            .all(|(a, b)| (a - b).abs() < 1e-6);
+        assert!(close, "{:?}", samples);
+    }
+
+    #[test]
+    fn world_emitters_only_run_near_the_origin() {
+        let world = World::new();
+        let origin = world.position_at_tile(0, 0, 0);
+        let bounds = Rect2::new_center_dim(V2::default(), V2::new(10.0, 10.0));
+        let mut particles = ParticleSystem::new(vec![]);
+        for &(x, z) in &[(1, 1), (40, 0), (1, 0)] {
+            let p = world.position_at_tile(x, 0, z);
+            particles.add_emitter(Emitter::new(EmitterAnchor::World(p), 0.0, 100.0, &DUST));
+        }
+        particles.recenter(&world, origin, bounds);
+        particles.update(0.1, |_| None);
+        assert_eq!(particles.particles.len(), 10);
+    }
+}
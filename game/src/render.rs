@@ -1,5 +1,14 @@
 use crate::host_api::*;
 use crate::V2;
+use std::marker::PhantomData;
+
+mod font;
+mod light;
+mod shapes;
+mod simd;
+
+pub use font::text_size;
+pub use light::*;
 
 #[repr(C)]
 pub struct OffscreenBuffer {
@@ -14,21 +23,64 @@ impl OffscreenBuffer {
     pub fn pitch(&self) -> usize {
         self.width * self.bytes_per_pixel
     }
+
+    pub fn target(&mut self) -> RenderTarget<'_> {
+        assert_eq!(self.bytes_per_pixel, BYTES_PER_PIXEL);
+        RenderTarget::new(&mut self.buffer, self.width, self.height)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Straight alpha, components in [0, 1]. `red`, `green` and `blue` are encoded like the bytes
+/// of a bitmap. Blending decodes them with gamma 2 rather than the exact sRGB curve: a square
+/// is cheap in the SIMD loops and close enough to sRGB for the game's art
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Color {
     pub red: f32,
     pub green: f32,
     pub blue: f32,
+    pub alpha: f32,
 }
 
 impl Color {
+    pub fn with_alpha(self, alpha: f32) -> Self {
+        Self { alpha, ..self }
+    }
+
+    /// B G R A bytes, premultiplied the same way bitmaps are at load time
+    fn premultiplied(&self) -> [u8; 4] {
+        let alpha = clamp01(self.alpha);
+        // gamma 2: linear * alpha, encoded back, is just the encoded value * sqrt(alpha)
+        let scale = 255.0 * alpha.sqrt();
+        let byte = |c: f32| (clamp01(c) * scale).round() as u8;
+        [
+            byte(self.blue),
+            byte(self.green),
+            byte(self.red),
+            (alpha * 255.0).round() as u8,
+        ]
+    }
+
+    /// Linear B G R A multipliers for `simd::blend_span`, the colors already scaled by alpha
+    fn tint(&self) -> [f32; 4] {
+        let alpha = clamp01(self.alpha);
+        let linear = |c: f32| {
+            let c = clamp01(c);
+            c * c * alpha
+        };
+        [
+            linear(self.blue),
+            linear(self.green),
+            linear(self.red),
+            alpha,
+        ]
+    }
+
     pub fn red() -> Self {
         Self {
             red: 1.0,
             green: 0.0,
             blue: 0.0,
+            alpha: 1.0,
         }
     }
 
@@ -37,6 +89,34 @@ impl Color {
             red: 1.0,
             green: 1.0,
             blue: 0.0,
+            alpha: 1.0,
+        }
+    }
+
+    pub fn green() -> Self {
+        Self {
+            red: 0.0,
+            green: 1.0,
+            blue: 0.0,
+            alpha: 1.0,
+        }
+    }
+
+    pub fn white() -> Self {
+        Self {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+            alpha: 1.0,
+        }
+    }
+
+    pub fn black() -> Self {
+        Self {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+            alpha: 1.0,
         }
     }
 
@@ -45,144 +125,715 @@ impl Color {
             red: 1.0,
             green: 0.0,
             blue: 1.0,
+            alpha: 1.0,
+        }
+    }
+}
+
+/// Exact color swaps applied to a bitmap while drawing it. Only opaque texels are matched, so
+/// antialiased edges keep their original color
+#[derive(Clone, Debug, Default)]
+pub struct Palette {
+    /// Sorted by `from`
+    swaps: Vec<(u32, u32)>,
+}
+
+impl Palette {
+    pub fn swap(mut self, from: Color, to: Color) -> Self {
+        let from = u32::from_le_bytes(from.with_alpha(1.0).premultiplied());
+        let to = u32::from_le_bytes(to.with_alpha(1.0).premultiplied());
+        match self.swaps.binary_search_by_key(&from, |&(from, _)| from) {
+            Ok(i) => self.swaps[i].1 = to,
+            Err(i) => self.swaps.insert(i, (from, to)),
+        }
+        self
+    }
+
+    /// Swaps every opaque color of `bitmaps` for `to`, e.g. to flash a sprite
+    pub fn flash(bitmaps: &[&Bitmap], to: Color) -> Self {
+        let mut opaque: Vec<u32> = bitmaps
+            .iter()
+            .flat_map(|bitmap| bitmap.pixels.chunks_exact(BYTES_PER_PIXEL))
+            .filter(|texel| texel[3] == 255)
+            .map(|texel| u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]))
+            .collect();
+        // in order, every swap lands at the end of the table
+        opaque.sort_unstable();
+        opaque.dedup();
+        opaque.into_iter().fold(Self::default(), |palette, value| {
+            let [blue, green, red, _] = value.to_le_bytes();
+            let from = Color {
+                red: red as f32 * INV_255,
+                green: green as f32 * INV_255,
+                blue: blue as f32 * INV_255,
+                alpha: 1.0,
+            };
+            palette.swap(from, to)
+        })
+    }
+
+    fn texel(&self, texel: &[u8]) -> [u8; 4] {
+        let value = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+        let value = match self.swaps.binary_search_by_key(&value, |&(from, _)| from) {
+            Ok(i) => self.swaps[i].1,
+            Err(_) => value,
+        };
+        value.to_le_bytes()
+    }
+
+    /// `out` is as long as `source`
+    fn remap(&self, source: &[u8], out: &mut [u8]) {
+        let texels = source.chunks_exact(BYTES_PER_PIXEL);
+        for (out, texel) in out.chunks_exact_mut(BYTES_PER_PIXEL).zip(texels) {
+            out.copy_from_slice(&self.texel(texel));
         }
     }
 }
 
+/// Pixels blended at once from a span on the stack: palette remaps, see-through rectangles
+const REMAP_SPAN: usize = 64;
+
 impl OffscreenBuffer {
     pub fn reset(&mut self) {
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let offset = y * self.pitch() + self.bytes_per_pixel * x;
-                // hideous magenta
-                self.buffer[offset + 0] = 255;
-                self.buffer[offset + 1] = 0;
-                self.buffer[offset + 2] = 255;
-                self.buffer[offset + 3] = 0;
-            }
-        }
+        assert_eq!(self.bytes_per_pixel, BYTES_PER_PIXEL);
+        let len = self.height * self.pitch();
+        // hideous magenta
+        simd::fill_span(&mut self.buffer[..len], 0x00FF_00FF);
     }
 
     pub fn render_rectangle(&mut self, min: V2, max: V2, color: Color) {
-        let miny = {
-            let miny = min.y().round() as i32;
-            if miny < 0 {
-                0
-            } else {
-                miny as usize
-            }
-        };
+        self.target().render_rectangle(min, max, color);
+    }
 
-        let minx = {
-            let minx = min.x().round() as i32;
-            if minx < 0 {
-                0
-            } else {
-                minx as usize
-            }
-        };
+    pub fn render_bitmap(
+        &mut self, bitmap: &Bitmap, xy: V2, color: Color, palette: Option<&Palette>,
+    ) {
+        self.target().render_bitmap(bitmap, xy, color, palette);
+    }
+
+    pub fn render_quad(
+        &mut self, bitmap: &Bitmap, quad: Quad, color: Color, palette: Option<&Palette>,
+    ) {
+        self.target().render_quad(bitmap, quad, color, palette);
+    }
+}
+
+const BYTES_PER_PIXEL: usize = 4;
+const INV_255: f32 = 1.0 / 255.0;
+
+/// Where a bitmap is mapped on the screen: `origin` is its top-left corner, `x_axis` (left to
+/// right along the bitmap) and `y_axis` (top to bottom) span its width and height
+#[derive(Copy, Clone, Debug)]
+pub struct Quad {
+    pub origin: V2,
+    pub x_axis: V2,
+    pub y_axis: V2,
+}
+
+/// Pixel bounds, `max` is exclusive
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ClipRect {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl ClipRect {
+    pub fn new(min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> Self {
+        Self {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    /// Pixels covered by `render_rectangle(min, max, ..)`
+    pub fn covering(min: V2, max: V2) -> Self {
+        Self::new(
+            min.x().round() as i32,
+            min.y().round() as i32,
+            max.x().round() as i32,
+            max.y().round() as i32,
+        )
+    }
+
+    pub fn intersect(&self, other: ClipRect) -> Self {
+        Self {
+            min_x: self.min_x.max(other.min_x),
+            min_y: self.min_y.max(other.min_y),
+            max_x: self.max_x.min(other.max_x),
+            max_y: self.max_y.min(other.max_y),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_x >= self.max_x || self.min_y >= self.max_y
+    }
+}
+
+/// Unique view over B G R A pixels, top row first.
+/// Nothing is ever written outside of `clip`: that is what makes disjoint tiles of the same
+/// buffer safe to render from different threads. `push_clip` can only narrow it
+pub struct RenderTarget<'a> {
+    memory: *mut u8,
+    width: usize,
+    height: usize,
+    /// Negative for bitmaps, which are stored bottom row first
+    pitch: isize,
+    clip: ClipRect,
+    /// Clips to restore on `pop_clip`
+    clip_stack: Vec<ClipRect>,
+    _pixels: PhantomData<&'a mut [u8]>,
+}
+
+unsafe impl<'a> Send for RenderTarget<'a> {
+}
+
+impl<'a> RenderTarget<'a> {
+    pub fn new(pixels: &'a mut [u8], width: usize, height: usize) -> Self {
+        assert!(pixels.len() >= width * height * BYTES_PER_PIXEL);
+        Self {
+            memory: pixels.as_mut_ptr(),
+            width,
+            height,
+            pitch: (width * BYTES_PER_PIXEL) as isize,
+            clip: ClipRect::new(0, 0, width as i32, height as i32),
+            clip_stack: Vec::new(),
+            _pixels: PhantomData,
+        }
+    }
+
+    /// Draws into `bitmap` as it is shown: `(0, 0)` is its top-left corner
+    pub fn from_bitmap(bitmap: &'a mut Bitmap) -> Self {
+        let pitch = bitmap.width * BYTES_PER_PIXEL;
+        assert!(bitmap.pixels.len() >= bitmap.height * pitch);
+        let top_row = bitmap.height.saturating_sub(1) * pitch;
+        Self {
+            memory: unsafe { bitmap.pixels.as_mut_ptr().add(top_row) },
+            width: bitmap.width,
+            height: bitmap.height,
+            pitch: -(pitch as isize),
+            clip: ClipRect::new(0, 0, bitmap.width as i32, bitmap.height as i32),
+            clip_stack: Vec::new(),
+            _pixels: PhantomData,
+        }
+    }
 
-        let maxx = {
-            let maxx = std::cmp::max(max.x().round() as i32, 0);
-            if maxx > self.width as i32 {
-                self.width as usize
-            } else {
-                maxx as usize
+    pub fn clip(&self) -> ClipRect {
+        self.clip
+    }
+
+    /// Clips everything drawn until the matching `pop_clip` to `rect`, on top of the current clip
+    pub fn push_clip(&mut self, rect: ClipRect) {
+        self.clip_stack.push(self.clip);
+        self.clip = self.clip.intersect(rect);
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.clip = self.clip_stack.pop().expect("pop_clip without push_clip");
+    }
+
+    /// Splits the target into a `count_x` by `count_y` grid of tiles that do not overlap, row
+    /// by row
+    pub fn into_tiles(
+        self, count_x: usize, count_y: usize,
+    ) -> impl Iterator<Item = RenderTarget<'a>> {
+        let tile_width = (self.width + count_x - 1) / count_x;
+        let tile_height = (self.height + count_y - 1) / count_y;
+        (0..count_x * count_y).map(move |i| {
+            let min_x = (i % count_x * tile_width) as i32;
+            let min_y = (i / count_x * tile_height) as i32;
+            let tile = ClipRect::new(
+                min_x,
+                min_y,
+                (min_x + tile_width as i32).min(self.width as i32),
+                (min_y + tile_height as i32).min(self.height as i32),
+            );
+            RenderTarget {
+                memory: self.memory,
+                width: self.width,
+                height: self.height,
+                pitch: self.pitch,
+                clip: self.clip.intersect(tile),
+                clip_stack: Vec::new(),
+                _pixels: PhantomData,
             }
-        };
+        })
+    }
+
+    /// `x` and `y` must be inside `clip`
+    fn pixel(&self, x: i32, y: i32) -> *mut u8 {
+        debug_assert!(x >= self.clip.min_x && x < self.clip.max_x);
+        debug_assert!(y >= self.clip.min_y && y < self.clip.max_y);
+        let offset = y as isize * self.pitch + x as isize * BYTES_PER_PIXEL as isize;
+        unsafe { self.memory.offset(offset) }
+    }
+
+    /// Pixels `min_x..max_x` of row `y`, which must be a non empty span inside `clip`
+    fn row(&mut self, min_x: i32, max_x: i32, y: i32) -> &mut [u8] {
+        let len = (max_x - min_x) as usize * BYTES_PER_PIXEL;
+        debug_assert!(max_x <= self.clip.max_x);
+        unsafe { std::slice::from_raw_parts_mut(self.pixel(min_x, y), len) }
+    }
+
+    pub fn render_rectangle(&mut self, min: V2, max: V2, color: Color) {
+        let min_x = (min.x().round() as i32).max(self.clip.min_x);
+        let min_y = (min.y().round() as i32).max(self.clip.min_y);
+        let max_x = (max.x().round() as i32).min(self.clip.max_x);
+        let max_y = (max.y().round() as i32).min(self.clip.max_y);
 
-        let maxy = {
-            let maxy = std::cmp::max(max.y().round() as i32, 0);
-            if maxy > self.height as i32 {
-                self.height as usize
-            } else {
-                maxy as usize
+        if min_x >= max_x {
+            return;
+        }
+
+        let pixel = color.premultiplied();
+        if color.alpha >= 1.0 {
+            let value = u32::from_le_bytes(pixel);
+            for y in min_y..max_y {
+                let dest = self.row(min_x, max_x, y);
+                simd::fill_span(dest, value);
             }
-        };
+        } else {
+            let mut source = [0; REMAP_SPAN * BYTES_PER_PIXEL];
+            for texel in source.chunks_exact_mut(BYTES_PER_PIXEL) {
+                texel.copy_from_slice(&pixel);
+            }
+            for y in min_y..max_y {
+                let dest = self.row(min_x, max_x, y);
+                for dest in dest.chunks_mut(source.len()) {
+                    simd::blend_span(dest, &source[..dest.len()], [1.0; 4]);
+                }
+            }
+        }
+    }
+
+    /// `color` is multiplied into every texel; white draws the bitmap as is
+    pub fn render_bitmap(
+        &mut self, bitmap: &Bitmap, xy: V2, color: Color, palette: Option<&Palette>,
+    ) {
+        let x0 = xy.x().round() as i32;
+        let y0 = xy.y().round() as i32;
+        let min_x = x0.max(self.clip.min_x);
+        let min_y = y0.max(self.clip.min_y);
+        let max_x = (x0 + bitmap.width as i32).min(self.clip.max_x);
+        let max_y = (y0 + bitmap.height as i32).min(self.clip.max_y);
+        if min_x >= max_x {
+            return;
+        }
 
-        for y in miny..maxy {
-            for x in minx..maxx {
-                let offset = y * self.pitch() + self.bytes_per_pixel * x;
-                self.buffer[offset + 0] = (color.blue * 255.0).round() as u8;
-                self.buffer[offset + 1] = (color.green * 255.0).round() as u8;
-                self.buffer[offset + 2] = (color.red * 255.0).round() as u8;
-                self.buffer[offset + 3] = 0;
+        let tint = color.tint();
+        for y in min_y..max_y {
+            // bitmaps are stored bottom row first
+            let source_row = (bitmap.height as i32 - 1 - (y - y0)) as usize;
+            let source_offset =
+                (source_row * bitmap.width + (min_x - x0) as usize) * BYTES_PER_PIXEL;
+            let source_len = (max_x - min_x) as usize * BYTES_PER_PIXEL;
+            let source = &bitmap.pixels[source_offset..source_offset + source_len];
+            let dest = self.row(min_x, max_x, y);
+            match palette {
+                // pixels blend independently, so a row can go through in pieces
+                Some(palette) => {
+                    let mut remapped = [0; REMAP_SPAN * BYTES_PER_PIXEL];
+                    let span = REMAP_SPAN * BYTES_PER_PIXEL;
+                    for (dest, source) in dest.chunks_mut(span).zip(source.chunks(span)) {
+                        let remapped = &mut remapped[..source.len()];
+                        palette.remap(source, remapped);
+                        simd::blend_span(dest, remapped, tint);
+                    }
+                }
+                None => simd::blend_span(dest, source, tint),
             }
         }
     }
 
-    pub fn render_bitmap(&mut self, bitmap: &Bitmap, xy: V2, c_alpha: f32) {
-        let real_x = xy.x();
-        let real_y = xy.y();
+    /// Maps `bitmap` onto `quad`, tinted and remapped like `render_bitmap`. Texels are filtered
+    /// bilinearly; outside of the bitmap they count as fully transparent, so edges fade over a
+    /// pixel instead of stair-stepping
+    pub fn render_quad(
+        &mut self, bitmap: &Bitmap, quad: Quad, color: Color, palette: Option<&Palette>,
+    ) {
+        let tint = color.tint();
+        self.fill_quad(bitmap, quad, palette, |_, _, _| tint);
+    }
+
+    /// The pixels of `render_quad`, each with the tint `tint_at` gives for its center and the
+    /// column and row (from the top) of the texel nearest to it
+    fn fill_quad(
+        &mut self, bitmap: &Bitmap, quad: Quad, palette: Option<&Palette>,
+        tint_at: impl Fn(V2, usize, usize) -> [f32; 4],
+    ) {
+        let Quad {
+            origin,
+            x_axis,
+            y_axis,
+        } = quad;
+        let det = x_axis.x() * y_axis.y() - x_axis.y() * y_axis.x();
+        if det == 0.0 || bitmap.width == 0 || bitmap.height == 0 {
+            return;
+        }
+        let width = bitmap.width as f32;
+        let height = bitmap.height as f32;
+        // half a texel of transparent border around the bitmap
+        let border_x = 0.5 / width;
+        let border_y = 0.5 / height;
+
+        let corners = [
+            origin,
+            origin + x_axis,
+            origin + y_axis,
+            origin + x_axis + y_axis,
+        ];
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for corner in &corners[1..] {
+            min = V2::new(min.x().min(corner.x()), min.y().min(corner.y()));
+            max = V2::new(max.x().max(corner.x()), max.y().max(corner.y()));
+        }
+        let min_x = ((min.x() - 1.0).floor() as i32).max(self.clip.min_x);
+        let min_y = ((min.y() - 1.0).floor() as i32).max(self.clip.min_y);
+        let max_x = ((max.x() + 1.0).ceil() as i32).min(self.clip.max_x);
+        let max_y = ((max.y() + 1.0).ceil() as i32).min(self.clip.max_y);
 
-        let (min_x, source_offset_x) = {
-            let min_x = real_x.round() as i32;
-            if min_x < 0 {
-                (0, -min_x as usize)
-            } else {
-                (min_x as usize, 0)
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = V2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let d = center - origin;
+                let u = (d.x() * y_axis.y() - d.y() * y_axis.x()) / det;
+                let v = (x_axis.x() * d.y() - x_axis.y() * d.x()) / det;
+                if u <= -border_x || v <= -border_y || u >= 1.0 + border_x || v >= 1.0 + border_y {
+                    continue;
+                }
+
+                let tx = u * width - 0.5;
+                let ty = v * height - 0.5;
+                let x0 = tx.floor();
+                let y0 = ty.floor();
+                let fx = tx - x0;
+                let fy = ty - y0;
+                let (x0, y0) = (x0 as i32, y0 as i32);
+                let texel = |x, y| texel(bitmap, x, y, palette);
+                let top = lerp4(texel(x0, y0), texel(x0 + 1, y0), fx);
+                let bottom = lerp4(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), fx);
+                let s = lerp4(top, bottom, fy);
+                if s[3] == 0.0 {
+                    continue;
+                }
+
+                let column = ((u * width) as usize).min(bitmap.width - 1);
+                let row = ((v * height) as usize).min(bitmap.height - 1);
+                let tint = tint_at(center, column, row);
+                unsafe { blend_pixel(self.pixel(x, y), s, tint) }
             }
+        }
+    }
+}
+
+/// Same math as `simd::blend_span`, for a single source texel that is already linear.
+/// `dest` must point to a whole pixel
+unsafe fn blend_pixel(dest: *mut u8, s: [f32; 4], tint: [f32; 4]) {
+    let sa = tint[3] * s[3];
+    let inv_sa = 1.0 - sa;
+    for channel in 0..3 {
+        let dc = *dest.offset(channel) as f32 * INV_255;
+        let c = inv_sa * (dc * dc) + tint[channel as usize] * s[channel as usize];
+        *dest.offset(channel) = (255.0 * c.sqrt() + 0.5) as u8;
+    }
+    let da = *dest.offset(3) as f32 * INV_255;
+    *dest.offset(3) = (255.0 * (sa + inv_sa * da) + 0.5) as u8;
+}
+
+/// Linear, premultiplied B G R A in [0, 1] of the texel at column `x`, row `y` counting from
+/// the top, through `palette`. Zero outside of the bitmap
+fn texel(bitmap: &Bitmap, x: i32, y: i32, palette: Option<&Palette>) -> [f32; 4] {
+    if x < 0 || y < 0 || x >= bitmap.width as i32 || y >= bitmap.height as i32 {
+        return [0.0; 4];
+    }
+    let texel = texel_bytes(bitmap, x as usize, y as usize);
+    match palette {
+        Some(palette) => linear_texel(&palette.texel(texel)),
+        None => linear_texel(texel),
+    }
+}
+
+/// Of the texel at `column`, `row` counting from the top
+fn texel_bytes(bitmap: &Bitmap, column: usize, row: usize) -> &[u8] {
+    // bitmaps are stored bottom row first
+    let row = bitmap.height - 1 - row;
+    let offset = (row * bitmap.width + column) * BYTES_PER_PIXEL;
+    &bitmap.pixels[offset..offset + BYTES_PER_PIXEL]
+}
+
+/// Linear, premultiplied B G R A in [0, 1] of the pixel `p`
+fn linear_texel(p: &[u8]) -> [f32; 4] {
+    let linear = |c: u8| {
+        let c = c as f32 * INV_255;
+        c * c
+    };
+    [
+        linear(p[0]),
+        linear(p[1]),
+        linear(p[2]),
+        p[3] as f32 * INV_255,
+    ]
+}
+
+/// Also turns NaN into 0
+fn clamp01(x: f32) -> f32 {
+    x.max(0.0).min(1.0)
+}
+
+fn lerp4(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for i in 0..4 {
+        result[i] = (1.0 - t) * a[i] + t * b[i];
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(width: usize, height: usize) -> Bitmap {
+        let pixels = (0..width * height)
+            .flat_map(|i| {
+                let c = (i * 37 % 256) as u8;
+                vec![c, 255 - c, c / 2, if i % 3 == 0 { 255 } else { c }]
+            })
+            .collect();
+        Bitmap {
+            align_x: 0,
+            align_y: 0,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn buffer(width: usize, height: usize) -> OffscreenBuffer {
+        let mut result = OffscreenBuffer {
+            buffer: vec![0; width * height * BYTES_PER_PIXEL],
+            width,
+            height,
+            bytes_per_pixel: BYTES_PER_PIXEL,
         };
+        result.reset();
+        result
+    }
 
-        let (min_y, source_offset_y) = {
-            let min_y = real_y.round() as i32;
-            if min_y < 0 {
-                (0, -min_y as usize)
-            } else {
-                (min_y as usize, 0)
-            }
+    /// As big as `bitmap`, at `p`
+    fn upright(bitmap: &Bitmap, p: V2) -> Quad {
+        Quad {
+            origin: p,
+            x_axis: V2::new(bitmap.width as f32, 0.0),
+            y_axis: V2::new(0.0, bitmap.height as f32),
+        }
+    }
+
+    #[test]
+    fn axis_aligned_quad_matches_bitmap() {
+        let bitmap = checker(7, 5);
+        let p = V2::new(3.0, 2.0);
+        let mut expected = buffer(16, 12);
+        let color = Color::white().with_alpha(0.75);
+        expected.render_bitmap(&bitmap, p, color, None);
+        let mut actual = buffer(16, 12);
+        actual.render_quad(&bitmap, upright(&bitmap, p), color, None);
+        assert_eq!(expected.buffer, actual.buffer);
+    }
+
+    #[test]
+    fn quad_stays_inside_clip() {
+        let bitmap = checker(7, 5);
+        let mut actual = buffer(16, 12);
+        let untouched = actual.buffer.clone();
+        let quad = Quad {
+            origin: V2::new(2.5, 1.5),
+            x_axis: V2::new(6.0, 4.0),
+            y_axis: V2::new(-2.0, 3.0),
         };
+        {
+            let mut tiles: Vec<_> = actual.target().into_tiles(2, 1).collect();
+            tiles[1].render_quad(&bitmap, quad, Color::white(), None);
+        }
+        let pitch = 16 * BYTES_PER_PIXEL;
+        for (row, untouched_row) in actual.buffer.chunks(pitch).zip(untouched.chunks(pitch)) {
+            assert_eq!(row[..pitch / 2], untouched_row[..pitch / 2]);
+        }
+        assert_ne!(actual.buffer, untouched);
+    }
 
-        let max_x = {
-            let max_x = std::cmp::max(real_x.round() as i32 + bitmap.width as i32, 0);
-            if max_x > self.width as i32 {
-                self.width as usize
-            } else {
-                max_x as usize
-            }
+    #[test]
+    fn bitmap_target_is_drawn_upright() {
+        let source = checker(5, 4);
+        let mut expected = buffer(9, 7);
+        expected.render_rectangle(V2::new(1.0, 0.0), V2::new(8.0, 3.0), Color::green());
+        expected.render_bitmap(&source, V2::new(3.0, 2.0), Color::white(), None);
+
+        let mut target = Bitmap {
+            align_x: 0,
+            align_y: 0,
+            width: 9,
+            height: 7,
+            pixels: vec![0; 9 * 7 * BYTES_PER_PIXEL],
         };
+        target.pixels.copy_from_slice(&buffer(9, 7).buffer);
+        {
+            let mut actual = RenderTarget::from_bitmap(&mut target);
+            actual.render_rectangle(V2::new(1.0, 0.0), V2::new(8.0, 3.0), Color::green());
+            actual.render_bitmap(&source, V2::new(3.0, 2.0), Color::white(), None);
+        }
+        let pitch = 9 * BYTES_PER_PIXEL;
+        let rows = target.pixels.chunks(pitch).rev();
+        for (actual, expected) in rows.zip(expected.buffer.chunks(pitch)) {
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn clip_stack_narrows_and_restores() {
+        let mut actual = buffer(8, 8);
+        let mut expected = buffer(8, 8);
+        {
+            let mut target = actual.target();
+            target.push_clip(ClipRect::new(2, 2, 6, 6));
+            target.push_clip(ClipRect::new(0, 0, 4, 8));
+            assert_eq!(target.clip(), ClipRect::new(2, 2, 4, 6));
+            target.render_rectangle(V2::new(0.0, 0.0), V2::new(8.0, 8.0), Color::red());
+            target.pop_clip();
+            target.pop_clip();
+            target.render_rectangle(V2::new(7.0, 7.0), V2::new(8.0, 8.0), Color::green());
+        }
+        expected.render_rectangle(V2::new(2.0, 2.0), V2::new(4.0, 6.0), Color::red());
+        expected.render_rectangle(V2::new(7.0, 7.0), V2::new(8.0, 8.0), Color::green());
+        assert_eq!(actual.buffer, expected.buffer);
+    }
 
-        let max_y = {
-            let max_y = std::cmp::max(real_y.round() as i32 + bitmap.height as i32, 0);
-            if max_y > self.height as i32 {
-                self.height as usize
-            } else {
-                max_y as usize
+    #[test]
+    fn line_stays_inside_clip() {
+        let mut actual = buffer(16, 12);
+        let untouched = actual.buffer.clone();
+        {
+            let mut tiles: Vec<_> = actual.target().into_tiles(2, 1).collect();
+            tiles[1].render_line(V2::new(-20.0, -20.0), V2::new(40.0, 40.0), Color::white());
+        }
+        let pitch = 16 * BYTES_PER_PIXEL;
+        let white = Color::white().premultiplied();
+        for (y, (row, untouched_row)) in actual
+            .buffer
+            .chunks(pitch)
+            .zip(untouched.chunks(pitch))
+            .enumerate()
+        {
+            assert_eq!(row[..pitch / 2], untouched_row[..pitch / 2]);
+            // the diagonal crosses the right half of the buffer
+            if y >= 8 {
+                let x = y * BYTES_PER_PIXEL;
+                assert_eq!(row[x..x + BYTES_PER_PIXEL], white);
             }
+        }
+    }
+
+    #[test]
+    fn line_covers_its_endpoints_once() {
+        let mut actual = buffer(16, 12);
+        let color = Color::green().with_alpha(0.5);
+        let (a, b) = (V2::new(2.5, 9.5), V2::new(13.5, 1.5));
+        actual.render_line(a, b, color);
+        let mut expected = buffer(16, 12);
+        for &p in &[a, b] {
+            expected.render_rectangle(p - V2::new(0.5, 0.5), p + V2::new(0.5, 0.5), color);
+        }
+        let pixel = |buffer: &OffscreenBuffer, p: V2| {
+            let i = (p.y() as usize * 16 + p.x() as usize) * BYTES_PER_PIXEL;
+            buffer.buffer[i..i + BYTES_PER_PIXEL].to_vec()
         };
+        assert_eq!(pixel(&actual, a), pixel(&expected, a));
+        assert_eq!(pixel(&actual, b), pixel(&expected, b));
+    }
+
+    #[test]
+    fn palette_swaps_opaque_texels() {
+        let mut bitmap = checker(6, 4);
+        bitmap.pixels[..BYTES_PER_PIXEL].copy_from_slice(&Color::red().premultiplied());
+        bitmap.pixels[BYTES_PER_PIXEL..2 * BYTES_PER_PIXEL]
+            .copy_from_slice(&Color::red().with_alpha(0.5).premultiplied());
+        let palette = Palette::default().swap(Color::red(), Color::green());
+        let p = V2::new(1.0, 1.0);
+        let mut actual = buffer(8, 8);
+        actual.render_bitmap(&bitmap, p, Color::white(), Some(&palette));
+
+        bitmap.pixels[..BYTES_PER_PIXEL].copy_from_slice(&Color::green().premultiplied());
+        let mut expected = buffer(8, 8);
+        expected.render_bitmap(&bitmap, p, Color::white(), None);
+        assert_eq!(expected.buffer, actual.buffer);
+    }
+
+    #[test]
+    fn flash_whitens_every_opaque_texel_of_long_rows() {
+        let mut bitmap = checker(REMAP_SPAN + 9, 3);
+        let palette = Palette::flash(&[&bitmap], Color::white());
+        let p = V2::new(2.0, 1.0);
+        let mut actual = buffer(REMAP_SPAN + 12, 5);
+        actual.render_bitmap(&bitmap, p, Color::white(), Some(&palette));
 
-        let mut source_offset_pixel = (bitmap.width * (bitmap.height - 1))
-            .saturating_sub(source_offset_y * bitmap.width)
-            + source_offset_x;
-
-        let mut dest_offset_pixel = min_y as usize * self.width + min_x as usize;
-        for _ in min_y..max_y {
-            let mut source_offset = source_offset_pixel * self.bytes_per_pixel;
-            let mut dest_offset = dest_offset_pixel * self.bytes_per_pixel;
-            for _ in min_x..max_x {
-                let sb = bitmap.pixels[source_offset + 0] as f32;
-                let sg = bitmap.pixels[source_offset + 1] as f32;
-                let sr = bitmap.pixels[source_offset + 2] as f32;
-                let a = c_alpha * (bitmap.pixels[source_offset + 3] as f32 / 255.0);
-
-                let db = self.buffer[dest_offset + 0] as f32;
-                let dg = self.buffer[dest_offset + 1] as f32;
-                let dr = self.buffer[dest_offset + 2] as f32;
-
-                let r = (1.0 - a) * dr + a * sr;
-                let g = (1.0 - a) * dg + a * sg;
-                let b = (1.0 - a) * db + a * sb;
-
-                self.buffer[dest_offset + 0] = (b + 0.5) as u8;
-                self.buffer[dest_offset + 1] = (g + 0.5) as u8;
-                self.buffer[dest_offset + 2] = (r + 0.5) as u8;
-                // self.buffer[dest_offset + 3] = 0;
-
-                source_offset += self.bytes_per_pixel;
-                dest_offset += self.bytes_per_pixel;
+        for texel in bitmap.pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+            if texel[3] == 255 {
+                texel.copy_from_slice(&Color::white().premultiplied());
             }
-            source_offset_pixel = source_offset_pixel.saturating_sub(bitmap.width);
-            dest_offset_pixel += self.width;
         }
+        let mut expected = buffer(REMAP_SPAN + 12, 5);
+        expected.render_bitmap(&bitmap, p, Color::white(), None);
+        assert_eq!(expected.buffer, actual.buffer);
+    }
+
+    #[test]
+    fn lit_quad_matches_lit_bitmap() {
+        let bitmap = checker(6, 4);
+        let normals = dome_normals(6, 4, 0.7);
+        let palette = Palette::flash(&[&bitmap], Color::green());
+        let lights = [Light {
+            p: V2::new(2.0, 3.0),
+            height: 2.0,
+            color: Color::yellow(),
+            radius: 8.0,
+        }];
+        let lighting = Lighting {
+            ambient: Color::white().with_alpha(0.2),
+            lights: &lights,
+        };
+        let p = V2::new(1.0, 2.0);
+        let color = Color::white().with_alpha(0.75);
+        let mut expected = buffer(8, 8);
+        expected
+            .target()
+            .render_bitmap_lit(&bitmap, &normals, p, color, Some(&palette), &lighting);
+        let mut actual = buffer(8, 8);
+        let quad = upright(&bitmap, p);
+        actual
+            .target()
+            .render_quad_lit(&bitmap, &normals, quad, color, Some(&palette), &lighting);
+        assert_eq!(expected.buffer, actual.buffer);
+    }
+
+    #[test]
+    fn unlit_normal_mapped_bitmap_matches_bitmap() {
+        let bitmap = checker(6, 4);
+        let normals = dome_normals(6, 4, 0.0);
+        let lighting = Lighting {
+            ambient: Color::white(),
+            lights: &[],
+        };
+        let p = V2::new(1.0, 2.0);
+        let color = Color::yellow().with_alpha(0.5);
+        let mut expected = buffer(8, 8);
+        expected.render_bitmap(&bitmap, p, color, None);
+        let mut actual = buffer(8, 8);
+        actual
+            .target()
+            .render_bitmap_lit(&bitmap, &normals, p, color, None, &lighting);
+        assert_eq!(expected.buffer, actual.buffer);
     }
 }
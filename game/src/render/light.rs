@@ -0,0 +1,188 @@
+use super::*;
+
+/// Point light, in `OffscreenBuffer` pixels. `height` is how far above the ground plane it
+/// floats; its contribution fades out completely at `radius`
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub p: V2,
+    pub height: f32,
+    pub color: Color,
+    pub radius: f32,
+}
+
+/// Everything that lights a frame. Alpha of the colors is ignored
+pub struct Lighting<'a> {
+    pub ambient: Color,
+    pub lights: &'a [Light],
+}
+
+/// Normal of anything without a normal map: straight at the viewer
+const FLAT: [f32; 3] = [0.0, 0.0, 1.0];
+
+impl<'a> Lighting<'a> {
+    /// Linear B G R light reaching a point `p` of the ground plane facing `normal`
+    /// (x to the right, y up the screen, z towards the viewer)
+    pub fn at(&self, p: V2, normal: [f32; 3]) -> [f32; 3] {
+        let linear = |color: Color| {
+            [
+                color.blue * color.blue,
+                color.green * color.green,
+                color.red * color.red,
+            ]
+        };
+        let mut result = linear(self.ambient);
+        for light in self.lights {
+            // screen y goes down, normals go up
+            let to_light = [light.p.x() - p.x(), p.y() - light.p.y(), light.height];
+            let distance = to_light.iter().map(|c| c * c).sum::<f32>().sqrt();
+            if distance >= light.radius || distance == 0.0 {
+                continue;
+            }
+            let n_dot_l = (0..3).map(|i| normal[i] * to_light[i]).sum::<f32>() / distance;
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let falloff = (1.0 - distance / light.radius).powi(2);
+            let color = linear(light.color);
+            for i in 0..3 {
+                result[i] += color[i] * n_dot_l * falloff;
+            }
+        }
+        result
+    }
+
+    /// `color` under the light reaching a flat sprite centered on `p`, saturating at white
+    pub fn shade(&self, color: Color, p: V2) -> Color {
+        let light = self.at(p, FLAT);
+        let shade = |c: f32, light: f32| (c * c * light).min(1.0).sqrt();
+        Color {
+            red: shade(color.red, light[2]),
+            green: shade(color.green, light[1]),
+            blue: shade(color.blue, light[0]),
+            alpha: color.alpha,
+        }
+    }
+}
+
+fn lit(tint: [f32; 4], light: [f32; 3]) -> [f32; 4] {
+    [
+        (tint[0] * light[0]).min(1.0),
+        (tint[1] * light[1]).min(1.0),
+        (tint[2] * light[2]).min(1.0),
+        tint[3],
+    ]
+}
+
+impl<'a> RenderTarget<'a> {
+    /// Like `render_bitmap`, shaded per pixel. `normals` has the size of `bitmap`, its R G B
+    /// hold the normal's x y z mapped from [-1, 1] to [0, 255] (see `Lighting::at`)
+    pub fn render_bitmap_lit(
+        &mut self, bitmap: &Bitmap, normals: &Bitmap, xy: V2, color: Color,
+        palette: Option<&Palette>, lighting: &Lighting,
+    ) {
+        assert_eq!(
+            (bitmap.width, bitmap.height),
+            (normals.width, normals.height)
+        );
+        let x0 = xy.x().round() as i32;
+        let y0 = xy.y().round() as i32;
+        let min_x = x0.max(self.clip.min_x);
+        let min_y = y0.max(self.clip.min_y);
+        let max_x = (x0 + bitmap.width as i32).min(self.clip.max_x);
+        let max_y = (y0 + bitmap.height as i32).min(self.clip.max_y);
+        if min_x >= max_x {
+            return;
+        }
+
+        let tint = color.tint();
+        for y in min_y..max_y {
+            // bitmaps are stored bottom row first
+            let source_row = (bitmap.height as i32 - 1 - (y - y0)) as usize;
+            let source_offset =
+                (source_row * bitmap.width + (min_x - x0) as usize) * BYTES_PER_PIXEL;
+            let source_len = (max_x - min_x) as usize * BYTES_PER_PIXEL;
+            let source = &bitmap.pixels[source_offset..source_offset + source_len];
+            let normal_row = &normals.pixels[source_offset..source_offset + source_len];
+
+            let texels = source.chunks_exact(BYTES_PER_PIXEL);
+            let normals = normal_row.chunks_exact(BYTES_PER_PIXEL);
+            for (x, (texel, normal)) in (min_x..max_x).zip(texels.zip(normals)) {
+                let s = match palette {
+                    Some(palette) => linear_texel(&palette.texel(texel)),
+                    None => linear_texel(texel),
+                };
+                if s[3] == 0.0 {
+                    continue;
+                }
+                let p = V2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let light = lighting.at(p, decode_normal(normal));
+                unsafe { blend_pixel(self.pixel(x, y), s, lit(tint, light)) }
+            }
+        }
+    }
+}
+
+impl<'a> RenderTarget<'a> {
+    /// Like `render_quad`, shaded per pixel. `normals` is laid out like in `render_bitmap_lit`,
+    /// taken from the texel nearest to each pixel and turned along with the quad
+    pub fn render_quad_lit(
+        &mut self, bitmap: &Bitmap, normals: &Bitmap, quad: Quad, color: Color,
+        palette: Option<&Palette>, lighting: &Lighting,
+    ) {
+        assert_eq!(
+            (bitmap.width, bitmap.height),
+            (normals.width, normals.height)
+        );
+        let tint = color.tint();
+        let x_unit = (1.0 / quad.x_axis.len()) * quad.x_axis;
+        let y_unit = (1.0 / quad.y_axis.len()) * quad.y_axis;
+        self.fill_quad(bitmap, quad, palette, |p, column, row| {
+            let [x, y, z] = decode_normal(texel_bytes(normals, column, row));
+            // the bitmap's x goes along `x_axis` and its y against `y_axis`, while lighting y
+            // goes up the screen
+            let normal = [
+                x * x_unit.x() - y * y_unit.x(),
+                y * y_unit.y() - x * x_unit.y(),
+                z,
+            ];
+            lit(tint, lighting.at(p, normal))
+        });
+    }
+}
+
+fn decode_normal(p: &[u8]) -> [f32; 3] {
+    let n = [
+        p[2] as f32 * INV_255 * 2.0 - 1.0,
+        p[1] as f32 * INV_255 * 2.0 - 1.0,
+        p[0] as f32 * INV_255 * 2.0 - 1.0,
+    ];
+    let len = n.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if len == 0.0 {
+        FLAT
+    } else {
+        [n[0] / len, n[1] / len, n[2] / len]
+    }
+}
+
+/// Normal map of a dome bulging towards the viewer, for sprites without a hand-made one.
+/// `bulge` goes from 0 (flat) to 1 (half a sphere)
+pub fn dome_normals(width: usize, height: usize, bulge: f32) -> Bitmap {
+    let mut pixels = Vec::with_capacity(width * height * BYTES_PER_PIXEL);
+    let encode = |c: f32| ((c + 1.0) * 0.5 * 255.0).round() as u8;
+    // bottom row first, like every bitmap
+    for row in (0..height).rev() {
+        for column in 0..width {
+            let nx = bulge * (2.0 * (column as f32 + 0.5) / width as f32 - 1.0);
+            let ny = -bulge * (2.0 * (row as f32 + 0.5) / height as f32 - 1.0);
+            let nz = (1.0 - (nx * nx + ny * ny).min(1.0)).sqrt();
+            pixels.extend_from_slice(&[encode(nz), encode(ny), encode(nx), 255]);
+        }
+    }
+    Bitmap {
+        align_x: 0,
+        align_y: 0,
+        width,
+        height,
+        pixels,
+    }
+}
@@ -0,0 +1,133 @@
+use super::*;
+
+/// Arrow heads are this long, unless the arrow is shorter than three of them
+const ARROW_HEAD: f32 = 6.0;
+/// Pixels per segment of a circle
+const CIRCLE_STEP: f32 = 4.0;
+
+impl<'a> RenderTarget<'a> {
+    /// One pixel wide. Pixel `(x, y)` covers `[x, x + 1) x [y, y + 1)`
+    pub fn render_line(&mut self, a: V2, b: V2, color: Color) {
+        let (a, b) = match self.clip_segment(a, b) {
+            Some(segment) => segment,
+            None => return,
+        };
+        let d = b - a;
+        let steps = d.x().abs().max(d.y().abs()).ceil().max(1.0);
+        let pixel = color.premultiplied();
+        let mut last = None;
+        for i in 0..=steps as i32 {
+            let p = a + (i as f32 / steps) * d;
+            let xy = (p.x().floor() as i32, p.y().floor() as i32);
+            // blending the same pixel twice would darken it
+            if last == Some(xy) {
+                continue;
+            }
+            last = Some(xy);
+            self.plot(xy.0, xy.1, pixel, color.alpha);
+        }
+    }
+
+    /// Inside edge of the rectangle `min..max`
+    pub fn render_outline(&mut self, min: V2, max: V2, color: Color) {
+        let thickness = 1.0;
+        let (left, top, right, bottom) = (min.x(), min.y(), max.x(), max.y());
+        self.render_rectangle(min, V2::new(right, top + thickness), color);
+        self.render_rectangle(V2::new(left, bottom - thickness), max, color);
+        let (top, bottom) = (top + thickness, bottom - thickness);
+        self.render_rectangle(V2::new(left, top), V2::new(left + thickness, bottom), color);
+        self.render_rectangle(
+            V2::new(right - thickness, top),
+            V2::new(right, bottom),
+            color,
+        );
+    }
+
+    pub fn render_circle(&mut self, center: V2, radius: f32, color: Color) {
+        let circumference = 2.0 * std::f32::consts::PI * radius;
+        let segments = (circumference / CIRCLE_STEP).ceil().max(8.0) as usize;
+        let at = |i: usize| {
+            let angle = 2.0 * std::f32::consts::PI * i as f32 / segments as f32;
+            center + radius * V2::new(angle.cos(), angle.sin())
+        };
+        for i in 0..segments {
+            self.render_line(at(i), at(i + 1), color);
+        }
+    }
+
+    /// Line with a head at `to`
+    pub fn render_arrow(&mut self, from: V2, to: V2, color: Color) {
+        self.render_line(from, to, color);
+        let d = to - from;
+        let len = d.len();
+        if len == 0.0 {
+            return;
+        }
+        let back = (-ARROW_HEAD.min(len / 3.0) / len) * d;
+        let side = 0.5 * back.perp();
+        self.render_line(to, to + back + side, color);
+        self.render_line(to, to + back - side, color);
+    }
+
+    /// The part of `a..b` inside `clip`, Liang-Barsky style
+    fn clip_segment(&self, a: V2, b: V2) -> Option<(V2, V2)> {
+        // keeps the far edges, which are exclusive, out
+        let epsilon = 0.001;
+        let d = b - a;
+        let mut t0: f32 = 0.0;
+        let mut t1: f32 = 1.0;
+        let edges = [
+            (-d.x(), a.x() - self.clip.min_x as f32),
+            (d.x(), self.clip.max_x as f32 - epsilon - a.x()),
+            (-d.y(), a.y() - self.clip.min_y as f32),
+            (d.y(), self.clip.max_y as f32 - epsilon - a.y()),
+        ];
+        for &(p, q) in edges.iter() {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+        if t0 > t1 {
+            None
+        } else {
+            Some((a + t0 * d, a + t1 * d))
+        }
+    }
+
+    fn plot(&mut self, x: i32, y: i32, pixel: [u8; 4], alpha: f32) {
+        let clip = self.clip;
+        if x < clip.min_x || y < clip.min_y || x >= clip.max_x || y >= clip.max_y {
+            return;
+        }
+        let dest = self.row(x, x + 1, y);
+        if alpha >= 1.0 {
+            dest.copy_from_slice(&pixel);
+        } else {
+            simd::blend_span(dest, &pixel, [1.0; 4]);
+        }
+    }
+}
+
+impl OffscreenBuffer {
+    pub fn render_line(&mut self, a: V2, b: V2, color: Color) {
+        self.target().render_line(a, b, color);
+    }
+
+    pub fn render_outline(&mut self, min: V2, max: V2, color: Color) {
+        self.target().render_outline(min, max, color);
+    }
+
+    pub fn render_circle(&mut self, center: V2, radius: f32, color: Color) {
+        self.target().render_circle(center, radius, color);
+    }
+
+    pub fn render_arrow(&mut self, from: V2, to: V2, color: Color) {
+        self.target().render_arrow(from, to, color);
+    }
+}
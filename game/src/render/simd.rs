@@ -0,0 +1,355 @@
+//! Inner pixel loops, with SSE2/AVX2 versions picked at runtime.
+//! Every path writes exactly the same bytes as the scalar one.
+//! Pixels are B G R A; spans are slices of whole pixels
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+const INV_255: f32 = 1.0 / 255.0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+impl SimdLevel {
+    /// Best level the running cpu supports
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return SimdLevel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return SimdLevel::Sse2;
+            }
+        }
+        SimdLevel::Scalar
+    }
+}
+
+/// Writes `value` (B G R A, little endian) into every pixel of `dest`
+pub fn fill_span(dest: &mut [u8], value: u32) {
+    fill_span_with(SimdLevel::detect(), dest, value)
+}
+
+/// Blends premultiplied `source` over `dest`, with every source channel multiplied by `tint`
+/// (B G R A, linear, the colors already scaled by its alpha).
+/// Colors are mixed in linear space: bytes are treated as gamma 2 encoded (squared on the way
+/// in, square rooted on the way out), an approximation of the sRGB curve. Alpha is linear
+pub fn blend_span(dest: &mut [u8], source: &[u8], tint: [f32; 4]) {
+    blend_span_with(SimdLevel::detect(), dest, source, tint)
+}
+
+pub fn fill_span_with(level: SimdLevel, dest: &mut [u8], value: u32) {
+    assert_eq!(dest.len() % 4, 0);
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 => unsafe { fill_span_avx2(dest, value) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { fill_span_sse2(dest, value) },
+        _ => fill_span_scalar(dest, value),
+    }
+}
+
+pub fn blend_span_with(level: SimdLevel, dest: &mut [u8], source: &[u8], tint: [f32; 4]) {
+    assert_eq!(dest.len() % 4, 0);
+    assert_eq!(dest.len(), source.len());
+    let mut tint = tint;
+    for t in &mut tint {
+        *t = super::clamp01(*t);
+    }
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 => unsafe { blend_span_avx2(dest, source, tint) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { blend_span_sse2(dest, source, tint) },
+        _ => blend_span_scalar(dest, source, tint),
+    }
+}
+
+fn fill_span_scalar(dest: &mut [u8], value: u32) {
+    let bytes = value.to_le_bytes();
+    for pixel in dest.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&bytes);
+    }
+}
+
+fn blend_span_scalar(dest: &mut [u8], source: &[u8], tint: [f32; 4]) {
+    for (d, s) in dest.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+        let sa = tint[3] * (s[3] as f32 * INV_255);
+        let inv_sa = 1.0 - sa;
+        for i in 0..3 {
+            let sc = s[i] as f32 * INV_255;
+            let dc = d[i] as f32 * INV_255;
+            let c = inv_sa * (dc * dc) + tint[i] * (sc * sc);
+            d[i] = (255.0 * c.sqrt() + 0.5) as u8;
+        }
+        let da = d[3] as f32 * INV_255;
+        d[3] = (255.0 * (sa + inv_sa * da) + 0.5) as u8;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn fill_span_sse2(dest: &mut [u8], value: u32) {
+    let wide = _mm_set1_epi32(value as i32);
+    let mut chunks = dest.chunks_exact_mut(16);
+    for chunk in &mut chunks {
+        _mm_storeu_si128(chunk.as_mut_ptr() as *mut __m128i, wide);
+    }
+    fill_span_scalar(chunks.into_remainder(), value);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn fill_span_avx2(dest: &mut [u8], value: u32) {
+    let wide = _mm256_set1_epi32(value as i32);
+    let mut chunks = dest.chunks_exact_mut(32);
+    for chunk in &mut chunks {
+        _mm256_storeu_si256(chunk.as_mut_ptr() as *mut __m256i, wide);
+    }
+    fill_span_scalar(chunks.into_remainder(), value);
+}
+
+// Same operations, in the same order, as `blend_span_scalar`. Clamping to [0, 255] before
+// truncating matches the saturating `as u8` (`max` also turns NaN into 0)
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn blend_span_sse2(dest: &mut [u8], source: &[u8], tint: [f32; 4]) {
+    let mask_ff = _mm_set1_epi32(0xFF);
+    let one = _mm_set1_ps(1.0);
+    let half = _mm_set1_ps(0.5);
+    let zero = _mm_set1_ps(0.0);
+    let max = _mm_set1_ps(255.0);
+    let inv_255 = _mm_set1_ps(INV_255);
+    let tint_b = _mm_set1_ps(tint[0]);
+    let tint_g = _mm_set1_ps(tint[1]);
+    let tint_r = _mm_set1_ps(tint[2]);
+    let tint_a = _mm_set1_ps(tint[3]);
+
+    let mut dest_chunks = dest.chunks_exact_mut(16);
+    let mut source_chunks = source.chunks_exact(16);
+    for (d, s) in (&mut dest_chunks).zip(&mut source_chunks) {
+        let s = _mm_loadu_si128(s.as_ptr() as *const __m128i);
+        let d_ptr = d.as_mut_ptr() as *mut __m128i;
+        let d = _mm_loadu_si128(d_ptr);
+
+        let channel = |pixels: __m128i, shift: i32| -> __m128 {
+            let shifted = match shift {
+                0 => pixels,
+                8 => _mm_srli_epi32(pixels, 8),
+                16 => _mm_srli_epi32(pixels, 16),
+                _ => _mm_srli_epi32(pixels, 24),
+            };
+            _mm_mul_ps(_mm_cvtepi32_ps(_mm_and_si128(shifted, mask_ff)), inv_255)
+        };
+        let to_byte = |c: __m128| -> __m128i {
+            let c = _mm_add_ps(_mm_mul_ps(max, c), half);
+            _mm_cvttps_epi32(_mm_min_ps(_mm_max_ps(c, zero), max))
+        };
+        let sa = _mm_mul_ps(tint_a, channel(s, 24));
+        let inv_sa = _mm_sub_ps(one, sa);
+        let blend = |dc: __m128, sc: __m128, tint: __m128| -> __m128i {
+            let c = _mm_add_ps(
+                _mm_mul_ps(inv_sa, _mm_mul_ps(dc, dc)),
+                _mm_mul_ps(tint, _mm_mul_ps(sc, sc)),
+            );
+            to_byte(_mm_sqrt_ps(c))
+        };
+        let blue = blend(channel(d, 0), channel(s, 0), tint_b);
+        let green = blend(channel(d, 8), channel(s, 8), tint_g);
+        let red = blend(channel(d, 16), channel(s, 16), tint_r);
+        let alpha = to_byte(_mm_add_ps(sa, _mm_mul_ps(inv_sa, channel(d, 24))));
+
+        let out = _mm_or_si128(
+            _mm_or_si128(blue, _mm_slli_epi32(green, 8)),
+            _mm_or_si128(_mm_slli_epi32(red, 16), _mm_slli_epi32(alpha, 24)),
+        );
+        _mm_storeu_si128(d_ptr, out);
+    }
+    blend_span_scalar(
+        dest_chunks.into_remainder(),
+        source_chunks.remainder(),
+        tint,
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn blend_span_avx2(dest: &mut [u8], source: &[u8], tint: [f32; 4]) {
+    let mask_ff = _mm256_set1_epi32(0xFF);
+    let one = _mm256_set1_ps(1.0);
+    let half = _mm256_set1_ps(0.5);
+    let zero = _mm256_set1_ps(0.0);
+    let max = _mm256_set1_ps(255.0);
+    let inv_255 = _mm256_set1_ps(INV_255);
+    let tint_b = _mm256_set1_ps(tint[0]);
+    let tint_g = _mm256_set1_ps(tint[1]);
+    let tint_r = _mm256_set1_ps(tint[2]);
+    let tint_a = _mm256_set1_ps(tint[3]);
+
+    let mut dest_chunks = dest.chunks_exact_mut(32);
+    let mut source_chunks = source.chunks_exact(32);
+    for (d, s) in (&mut dest_chunks).zip(&mut source_chunks) {
+        let s = _mm256_loadu_si256(s.as_ptr() as *const __m256i);
+        let d_ptr = d.as_mut_ptr() as *mut __m256i;
+        let d = _mm256_loadu_si256(d_ptr);
+
+        let channel = |pixels: __m256i, shift: i32| -> __m256 {
+            let shifted = match shift {
+                0 => pixels,
+                8 => _mm256_srli_epi32(pixels, 8),
+                16 => _mm256_srli_epi32(pixels, 16),
+                _ => _mm256_srli_epi32(pixels, 24),
+            };
+            _mm256_mul_ps(
+                _mm256_cvtepi32_ps(_mm256_and_si256(shifted, mask_ff)),
+                inv_255,
+            )
+        };
+        let to_byte = |c: __m256| -> __m256i {
+            let c = _mm256_add_ps(_mm256_mul_ps(max, c), half);
+            _mm256_cvttps_epi32(_mm256_min_ps(_mm256_max_ps(c, zero), max))
+        };
+        let sa = _mm256_mul_ps(tint_a, channel(s, 24));
+        let inv_sa = _mm256_sub_ps(one, sa);
+        let blend = |dc: __m256, sc: __m256, tint: __m256| -> __m256i {
+            let c = _mm256_add_ps(
+                _mm256_mul_ps(inv_sa, _mm256_mul_ps(dc, dc)),
+                _mm256_mul_ps(tint, _mm256_mul_ps(sc, sc)),
+            );
+            to_byte(_mm256_sqrt_ps(c))
+        };
+        let blue = blend(channel(d, 0), channel(s, 0), tint_b);
+        let green = blend(channel(d, 8), channel(s, 8), tint_g);
+        let red = blend(channel(d, 16), channel(s, 16), tint_r);
+        let alpha = to_byte(_mm256_add_ps(sa, _mm256_mul_ps(inv_sa, channel(d, 24))));
+
+        let out = _mm256_or_si256(
+            _mm256_or_si256(blue, _mm256_slli_epi32(green, 8)),
+            _mm256_or_si256(_mm256_slli_epi32(red, 16), _mm256_slli_epi32(alpha, 24)),
+        );
+        _mm256_storeu_si256(d_ptr, out);
+    }
+    blend_span_scalar(
+        dest_chunks.into_remainder(),
+        source_chunks.remainder(),
+        tint,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels() -> Vec<SimdLevel> {
+        let mut result = vec![SimdLevel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse2") {
+                result.push(SimdLevel::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                result.push(SimdLevel::Avx2);
+            }
+        }
+        result
+    }
+
+    // xorshift, so the tests need no extra dependencies
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fill_matches_scalar() {
+        for pixels in 0..37 {
+            let mut expected = noise(pixels * 4, 1);
+            fill_span_with(SimdLevel::Scalar, &mut expected, 0x00FF_00FF);
+            for &level in &levels() {
+                let mut actual = noise(pixels * 4, 1);
+                fill_span_with(level, &mut actual, 0x00FF_00FF);
+                assert_eq!(expected, actual, "{:?} with {} pixels", level, pixels);
+            }
+        }
+    }
+
+    #[test]
+    fn blend_matches_scalar() {
+        let tints = [
+            [0.0; 4],
+            [0.25; 4],
+            [1.0; 4],
+            [1.5; 4],
+            [-0.5; 4],
+            [0.1, 0.2, 0.9, 0.9],
+            [1.0, 0.3, 0.3, 1.0],
+        ];
+        for pixels in 0..37 {
+            let source = noise(pixels * 4, 7);
+            for &tint in &tints {
+                let mut expected = noise(pixels * 4, 3);
+                blend_span_with(SimdLevel::Scalar, &mut expected, &source, tint);
+                for &level in &levels() {
+                    let mut actual = noise(pixels * 4, 3);
+                    blend_span_with(level, &mut actual, &source, tint);
+                    assert_eq!(
+                        expected, actual,
+                        "{:?} with {} pixels and tint {:?}",
+                        level, pixels, tint
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blend_matches_scalar_for_every_alpha() {
+        let dest: Vec<u8> = (0..256u32)
+            .flat_map(|a| vec![a as u8, 255 - a as u8, 128, 77])
+            .collect();
+        let source: Vec<u8> = (0..256u32)
+            .flat_map(|a| vec![255 - a as u8, a as u8, 3, a as u8])
+            .collect();
+        let mut expected = dest.clone();
+        blend_span_with(SimdLevel::Scalar, &mut expected, &source, [1.0; 4]);
+        for &level in &levels() {
+            let mut actual = dest.clone();
+            blend_span_with(level, &mut actual, &source, [1.0; 4]);
+            assert_eq!(expected, actual, "{:?}", level);
+        }
+    }
+
+    #[test]
+    fn linear_round_trip_is_exact() {
+        let dest = noise(64 * 4, 5);
+        let mut source = noise(64 * 4, 9);
+        for &level in &levels() {
+            let mut transparent = dest.clone();
+            blend_span_with(level, &mut transparent, &vec![0; dest.len()], [1.0; 4]);
+            let mut faded = dest.clone();
+            blend_span_with(level, &mut faded, &source, [0.0; 4]);
+            for (&t, &d) in transparent.iter().zip(&dest) {
+                assert_eq!(t, d, "{:?}", level);
+            }
+            assert_eq!(faded, dest, "{:?}", level);
+
+            for pixel in source.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+            let mut opaque = dest.clone();
+            blend_span_with(level, &mut opaque, &source, [1.0; 4]);
+            assert_eq!(opaque, source, "{:?}", level);
+        }
+    }
+}
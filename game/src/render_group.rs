@@ -0,0 +1,245 @@
+use super::*;
+use render::{Light, Lighting, Palette, Quad, RenderTarget};
+use std::cmp::Ordering;
+use std::sync::Mutex;
+
+const TILE_COUNT_X: usize = 4;
+const TILE_COUNT_Y: usize = 4;
+/// Lights of a frame, pushing more panics
+pub const MAX_LIGHTS: usize = 32;
+
+/// Positions are in `OffscreenBuffer` pixels
+pub enum RenderCommand<'a> {
+    Clear {
+        color: Color,
+    },
+    Rectangle {
+        min: V2,
+        max: V2,
+        color: Color,
+    },
+    /// `p` is the top-left corner of the bitmap, `color` tints it. Without `normals` the whole
+    /// bitmap is lit as one flat surface
+    Bitmap {
+        bitmap: &'a Bitmap,
+        normals: Option<&'a Bitmap>,
+        p: V2,
+        color: Color,
+        palette: Option<&'a Palette>,
+    },
+    /// Like `Bitmap`, mapped onto `quad`
+    Quad {
+        bitmap: &'a Bitmap,
+        normals: Option<&'a Bitmap>,
+        quad: Quad,
+        color: Color,
+        palette: Option<&'a Palette>,
+    },
+}
+
+/// Where a command sits in depth. Commands are drawn back to front: lower `floor` first,
+/// then larger sim space `y` (further up the screen), then lower `z`, then in push order
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SortKey {
+    /// Relative to the camera's floor
+    pub floor: i32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl SortKey {
+    pub fn new(y: f32, z: f32) -> Self {
+        Self { floor: 0, y, z }
+    }
+
+    pub fn on_floor(mut self, floor: i32) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Behind every entity of its floor, in push order
+    pub fn background() -> Self {
+        Self::new(std::f32::INFINITY, std::f32::NEG_INFINITY)
+    }
+}
+
+struct Entry<'a> {
+    key: SortKey,
+    order: usize,
+    command: RenderCommand<'a>,
+}
+
+/// Gameplay pushes commands while simulating, then `draw` sorts and renders them in one pass.
+/// Bitmaps are lit by the ambient color plus every pushed light; rectangles are not
+pub struct RenderGroup<'a> {
+    arena: &'a Arena,
+    entries: ArenaStack<'a, Entry<'a>>,
+    ambient: Color,
+    lights: ArenaStack<'a, Light>,
+}
+
+impl<'a> RenderGroup<'a> {
+    pub fn new(arena: &'a Arena, max_commands: usize) -> Self {
+        Self {
+            arena,
+            entries: arena.stack(max_commands),
+            ambient: Color::white(),
+            lights: arena.stack(MAX_LIGHTS),
+        }
+    }
+
+    pub fn set_ambient(&mut self, ambient: Color) {
+        self.ambient = ambient;
+    }
+
+    pub fn push_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    /// Always drawn before everything else
+    pub fn clear(&mut self, color: Color) {
+        let key = SortKey::background().on_floor(i32::MIN);
+        self.push(key, RenderCommand::Clear { color });
+    }
+
+    pub fn push_rectangle(&mut self, key: SortKey, min: V2, max: V2, color: Color) {
+        self.push(key, RenderCommand::Rectangle { min, max, color });
+    }
+
+    pub fn push_bitmap_with(
+        &mut self, key: SortKey, bitmap: &'a Bitmap, normals: Option<&'a Bitmap>, p: V2,
+        color: Color, palette: Option<&'a Palette>,
+    ) {
+        self.push(
+            key,
+            RenderCommand::Bitmap {
+                bitmap,
+                normals,
+                p,
+                color,
+                palette,
+            },
+        );
+    }
+
+    pub fn push_quad(
+        &mut self, key: SortKey, bitmap: &'a Bitmap, normals: Option<&'a Bitmap>, quad: Quad,
+        color: Color, palette: Option<&'a Palette>,
+    ) {
+        self.push(
+            key,
+            RenderCommand::Quad {
+                bitmap,
+                normals,
+                quad,
+                color,
+                palette,
+            },
+        );
+    }
+
+    fn push(&mut self, key: SortKey, command: RenderCommand<'a>) {
+        let order = self.entries.len();
+        self.entries.push(Entry {
+            key,
+            order,
+            command,
+        });
+    }
+
+    /// Sorts, then renders the screen as a grid of tiles in parallel. Every tile runs the whole
+    /// command list clipped to its bounds, so the result matches `draw_single_threaded`
+    pub fn draw(mut self, buffer: &mut OffscreenBuffer, host_api: &dyn HostApi) {
+        self.sort();
+        let mut tiles = self.arena.stack(TILE_COUNT_X * TILE_COUNT_Y);
+        for tile in buffer.target().into_tiles(TILE_COUNT_X, TILE_COUNT_Y) {
+            tiles.push(Mutex::new(tile));
+        }
+        let entries: &[Entry] = &self.entries;
+        let lighting = self.lighting();
+        host_api.parallel_for(tiles.len(), &|i| {
+            let mut target = tiles[i].lock().unwrap();
+            render(entries, &lighting, &mut target);
+        });
+    }
+
+    #[cfg(test)]
+    pub fn draw_single_threaded(mut self, buffer: &mut OffscreenBuffer) {
+        self.sort();
+        render(&self.entries, &self.lighting(), &mut buffer.target());
+    }
+
+    fn lighting(&self) -> Lighting<'_> {
+        Lighting {
+            ambient: self.ambient,
+            lights: &self.lights,
+        }
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_unstable_by(|a, b| {
+            a.key
+                .floor
+                .cmp(&b.key.floor)
+                .then(b.key.y.partial_cmp(&a.key.y).unwrap_or(Ordering::Equal))
+                .then(a.key.z.partial_cmp(&b.key.z).unwrap_or(Ordering::Equal))
+                .then(a.order.cmp(&b.order))
+        });
+    }
+}
+
+fn render(entries: &[Entry], lighting: &Lighting, target: &mut RenderTarget) {
+    for entry in entries {
+        match entry.command {
+            RenderCommand::Clear { color } => {
+                let clip = target.clip();
+                let min = V2::new(clip.min_x as f32, clip.min_y as f32);
+                let max = V2::new(clip.max_x as f32, clip.max_y as f32);
+                target.render_rectangle(min, max, color);
+            }
+            RenderCommand::Rectangle { min, max, color } => {
+                target.render_rectangle(min, max, color);
+            }
+            RenderCommand::Bitmap {
+                bitmap,
+                normals: Some(normals),
+                p,
+                color,
+                palette,
+            } => {
+                target.render_bitmap_lit(bitmap, normals, p, color, palette, lighting);
+            }
+            RenderCommand::Bitmap {
+                bitmap,
+                normals: None,
+                p,
+                color,
+                palette,
+            } => {
+                let center = p + 0.5 * V2::new(bitmap.width as f32, bitmap.height as f32);
+                let color = lighting.shade(color, center);
+                target.render_bitmap(bitmap, p, color, palette);
+            }
+            RenderCommand::Quad {
+                bitmap,
+                normals: Some(normals),
+                quad,
+                color,
+                palette,
+            } => {
+                target.render_quad_lit(bitmap, normals, quad, color, palette, lighting);
+            }
+            RenderCommand::Quad {
+                bitmap,
+                normals: None,
+                quad,
+                color,
+                palette,
+            } => {
+                let center = quad.origin + 0.5 * (quad.x_axis + quad.y_axis);
+                let color = lighting.shade(color, center);
+                target.render_quad(bitmap, quad, color, palette);
+            }
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Maps made with the Tiled editor, from its JSON export, loaded like `level` files when
+//! `GAME_LEVEL` ends with `.json`.
+//!
+//! Tiles of the map's tilesets tell what they are with custom properties: `ground`, `dirt`
+//! (the default) or `grass`, and `wall`, a bool. Tiles with neither are left out, so they can
+//! be used for decoration. Tile layers must be exported as CSV, and tilesets embedded.
+//!
+//! Object layers hold the spawns, by their class (type before Tiled 1.9): `player`,
+//! `monster`, `familiar`, `torch`, `stairs` or `ladder`, with the options of `level` files as
+//! custom properties. An object stands on the tile under its center.
+//!
+//! Layers are on floor 0, unless an int `floor` property on them or a group they are in says
+//! otherwise
+
+use super::json::{self, Json};
+use super::*;
+use std::collections::HashMap;
+
+pub fn load(text: &str) -> Result<World, String> {
+    let map = json::parse(text).map_err(|e| e.to_string())?;
+    let orientation = map.get("orientation").and_then(Json::as_str);
+    if orientation.map_or(false, |o| o != "orthogonal") {
+        return Err(format!(
+            "{} maps aren't supported",
+            orientation.unwrap_or("")
+        ));
+    }
+    let mut importer = Importer {
+        world: World::new(),
+        tiles: tiles(&map)?,
+        height: number(&map, "height", "map")? as i32,
+        tile_dim: (
+            number(&map, "tilewidth", "map")?,
+            number(&map, "tileheight", "map")?,
+        ),
+        placed: vec![],
+        player: false,
+    };
+    importer.add_layers(&map, 0)?;
+    if !importer.player {
+        return Err("no player object".to_owned());
+    }
+    for (tile, what) in &importer.placed {
+        if let Some(problem) = level::misplaced(&importer.world, *tile) {
+            return Err(format!("{}: {}", what, problem));
+        }
+    }
+    Ok(importer.world)
+}
+
+struct Importer {
+    world: World,
+    /// By global tile id
+    tiles: HashMap<u32, MapTile>,
+    /// Of the map, in tiles: Tiled rows go down, from the top
+    height: i32,
+    tile_dim: (f64, f64),
+    /// Tiles that have to be open floor, and what stands there
+    placed: Vec<((i32, i32, i32), String)>,
+    player: bool,
+}
+
+impl Importer {
+    /// Of `parent`, the map or a group layer on `floor`
+    fn add_layers(&mut self, parent: &Json, floor: i32) -> Result<(), String> {
+        let layers = parent.get("layers").and_then(Json::as_array).unwrap_or(&[]);
+        for layer in layers {
+            let name = layer.get("name").and_then(Json::as_str).unwrap_or("");
+            let what = format!("layer `{}`", name);
+            let floor = match property(layer, "floor") {
+                Some(value) => whole(value, &what, "floor")?,
+                None => floor,
+            };
+            match layer.get("type").and_then(Json::as_str) {
+                Some("tilelayer") => {
+                    if layer.get("encoding").and_then(Json::as_str) == Some("base64") {
+                        return Err(format!("{}: export the tile layer format as CSV", what));
+                    }
+                    match layer.get("chunks").and_then(Json::as_array) {
+                        // infinite maps
+                        Some(chunks) => {
+                            for chunk in chunks {
+                                self.add_tiles(chunk, floor, &what)?;
+                            }
+                        }
+                        None => self.add_tiles(layer, floor, &what)?,
+                    }
+                }
+                Some("objectgroup") => {
+                    let objects = layer.get("objects").and_then(Json::as_array).unwrap_or(&[]);
+                    for object in objects {
+                        self.add_object(object, floor)?;
+                    }
+                }
+                Some("group") => self.add_layers(layer, floor)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `data` of a tile layer or chunk, `x` and `y` being where it starts, in tiles
+    fn add_tiles(&mut self, layer: &Json, floor: i32, what: &str) -> Result<(), String> {
+        let (x, y) = (
+            layer.get("x").and_then(Json::as_f64).unwrap_or(0.0) as i32,
+            layer.get("y").and_then(Json::as_f64).unwrap_or(0.0) as i32,
+        );
+        let width = number(layer, "width", what)? as usize;
+        let data = layer
+            .get("data")
+            .and_then(Json::as_array)
+            .ok_or_else(|| format!("{}: no `data`", what))?;
+        if width == 0 {
+            return Ok(());
+        }
+        for (row, ids) in data.chunks(width).enumerate() {
+            let tiles: Vec<_> = ids
+                .iter()
+                .map(|id| {
+                    // the top bits say how the tile is flipped
+                    let gid = id.as_f64().unwrap_or(0.0) as u32 & 0x0fff_ffff;
+                    self.tiles.get(&gid).copied()
+                })
+                .collect();
+            let row = y + row as i32;
+            self.world
+                .add_row((x, self.height - 1 - row, floor), &tiles);
+        }
+        Ok(())
+    }
+
+    fn add_object(&mut self, object: &Json, floor: i32) -> Result<(), String> {
+        let id = object.get("id").and_then(Json::as_f64).unwrap_or(0.0);
+        let class = object
+            .get("class")
+            .or_else(|| object.get("type"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let what = format!("object {} (`{}`)", id, class);
+        let keys: &[&str] = match class {
+            "player" | "monster" => &["hit_points"],
+            "familiar" => &[],
+            "torch" => &["height", "radius", "color"],
+            "stairs" => &["length", "climb"],
+            "ladder" => &["climb"],
+            _ => return Err(format!("{}: unknown class", what)),
+        };
+        let properties = object
+            .get("properties")
+            .and_then(Json::as_array)
+            .unwrap_or(&[]);
+        for p in properties {
+            let name = p.get("name").and_then(Json::as_str).unwrap_or("");
+            if !keys.contains(&name) {
+                return Err(format!(
+                    "{}: unknown property `{}`, expected {}",
+                    what,
+                    name,
+                    keys.join(", ")
+                ));
+            }
+        }
+
+        let position = |key| object.get(key).and_then(Json::as_f64).unwrap_or(0.0);
+        let (width, height) = (position("width"), position("height"));
+        // tile objects hang from their bottom-left corner, the others from their top-left one
+        let center_y = if object.get("gid").is_some() {
+            position("y") - 0.5 * height
+        } else {
+            position("y") + 0.5 * height
+        };
+        let column = ((position("x") + 0.5 * width) / self.tile_dim.0).floor() as i32;
+        let row = (center_y / self.tile_dim.1).floor() as i32;
+        let tile = (column, self.height - 1 - row, floor);
+
+        let get = |key: &str| property(object, key);
+        let hit_points = |default: usize| match get("hit_points") {
+            Some(value) => match whole(value, &what, "hit_points")? {
+                n if n > 0 && n as usize <= MAX_HIT_POINTS => Ok(n as usize),
+                _ => Err(format!(
+                    "{}: hit_points has to be from 1 to {}",
+                    what, MAX_HIT_POINTS
+                )),
+            },
+            None => Ok(default),
+        };
+        let mut placed = vec![tile];
+        match class {
+            "player" => {
+                let hit_points = hit_points(PLAYER_HIT_POINTS)?;
+                self.world.spawns.push(Spawn::Player { tile, hit_points });
+                self.player = true;
+            }
+            "monster" => {
+                let hit_points = hit_points(MONSTER_HIT_POINTS)?;
+                self.world.spawns.push(Spawn::Monster { tile, hit_points });
+            }
+            "familiar" => {
+                self.world.spawns.push(Spawn::Familiar { tile });
+            }
+            "torch" => {
+                let meters = |key, default| match get(key) {
+                    Some(value) => value
+                        .as_f64()
+                        .filter(|&m| m >= 0.0)
+                        .map(|m| m as f32)
+                        .ok_or_else(|| format!("{}: {} has to be a number, 0 or more", what, key)),
+                    None => Ok(default),
+                };
+                let height = meters("height", TORCH_HEIGHT)?;
+                let radius = meters("radius", TORCH_RADIUS)?;
+                let color = match get("color") {
+                    Some(value) => color(value).ok_or_else(|| format!("{}: bad color", what))?,
+                    None => TORCHES[0],
+                };
+                self.world.add_torch(tile, height, color, radius);
+                placed.clear();
+            }
+            _ => {
+                let kind = if class == "ladder" {
+                    StairsKind::Ladder
+                } else {
+                    StairsKind::Stairs
+                };
+                let length = match get("length") {
+                    Some(value) => match whole(value, &what, "length")? {
+                        n if n > 0 && n <= MAX_STAIRS_LENGTH => n,
+                        _ => {
+                            return Err(format!(
+                                "{}: length has to be from 1 to {}",
+                                what, MAX_STAIRS_LENGTH
+                            ))
+                        }
+                    },
+                    None if kind == StairsKind::Ladder => 1,
+                    None => STAIRS_LENGTH,
+                };
+                let climb = match get("climb") {
+                    Some(value) => match whole(value, &what, "climb")? {
+                        0 => return Err(format!("{}: climb can't be 0", what)),
+                        n => n,
+                    },
+                    None => 1,
+                };
+                self.world.add_stairs(kind, tile, length, climb);
+                let (x, y, z) = tile;
+                placed = (0..length)
+                    .flat_map(|i| vec![(x, y + i, z), (x, y + i, z + climb)])
+                    .collect();
+            }
+        }
+        // tile layers may come after object layers, so this waits for them
+        self.placed
+            .extend(placed.into_iter().map(|tile| (tile, what.clone())));
+        Ok(())
+    }
+}
+
+/// What each tile of the embedded tilesets is, by global tile id
+fn tiles(map: &Json) -> Result<HashMap<u32, MapTile>, String> {
+    let mut result = HashMap::new();
+    let tilesets = map.get("tilesets").and_then(Json::as_array).unwrap_or(&[]);
+    for tileset in tilesets {
+        if let Some(source) = tileset.get("source").and_then(Json::as_str) {
+            return Err(format!("tileset {}: embed it in the map", source));
+        }
+        let first = number(tileset, "firstgid", "tileset")? as u32;
+        let tiles = tileset.get("tiles").and_then(Json::as_array).unwrap_or(&[]);
+        for tile in tiles {
+            let id = number(tile, "id", "tile")? as u32;
+            let what = format!("tile {}", first + id);
+            let wall = match property(tile, "wall") {
+                Some(value) => value
+                    .as_bool()
+                    .ok_or_else(|| format!("{}: wall has to be a bool", what))?,
+                None => false,
+            };
+            let ground = match property(tile, "ground").map(|g| g.as_str()) {
+                Some(Some("dirt")) => Ground::Dirt,
+                Some(Some("grass")) => Ground::Grass,
+                Some(_) => return Err(format!("{}: ground has to be dirt or grass", what)),
+                None if wall => Ground::Dirt,
+                None => continue,
+            };
+            result.insert(first + id, MapTile { ground, wall });
+        }
+    }
+    Ok(result)
+}
+
+/// Value of the custom property `name` of `json`
+fn property<'a>(json: &'a Json, name: &str) -> Option<&'a Json> {
+    json.get("properties")
+        .and_then(Json::as_array)?
+        .iter()
+        .find(|p| p.get("name").and_then(Json::as_str) == Some(name))
+        .and_then(|p| p.get("value"))
+}
+
+fn number(json: &Json, key: &str, what: &str) -> Result<f64, String> {
+    json.get(key)
+        .and_then(Json::as_f64)
+        .ok_or_else(|| format!("{}: no `{}`", what, key))
+}
+
+fn whole(value: &Json, what: &str, key: &str) -> Result<i32, String> {
+    value
+        .as_f64()
+        .filter(|n| n.fract() == 0.0)
+        .map(|n| n as i32)
+        .ok_or_else(|| format!("{}: {} has to be a whole number", what, key))
+}
+
+/// Tiled writes colors `#aarrggbb`, or `#rrggbb`
+fn color(value: &Json) -> Option<Color> {
+    let hex = value.as_str()?.strip_prefix('#')?;
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+    let channel = |i: usize| {
+        let digits = hex.get(hex.len() - 6 + 2 * i..hex.len() - 4 + 2 * i)?;
+        u8::from_str_radix(digits, 16)
+            .ok()
+            .map(|c| c as f32 / 255.0)
+    };
+    Some(Color {
+        red: channel(0)?,
+        green: channel(1)?,
+        blue: channel(2)?,
+        alpha: 1.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A room with a door to the west, and a loft above it
+    const MAP: &str = r##"{
+ "orientation": "orthogonal", "width": 5, "height": 4, "tilewidth": 16, "tileheight": 16,
+ "tilesets": [{"firstgid": 1, "tiles": [
+   {"id": 0, "properties": [{"name": "wall", "type": "bool", "value": true}]},
+   {"id": 1, "properties": [{"name": "ground", "type": "string", "value": "dirt"}]},
+   {"id": 2, "properties": [{"name": "ground", "type": "string", "value": "grass"}]}
+ ]}],
+ "layers": [
+  {"type": "tilelayer", "name": "ground", "x": 0, "y": 0, "width": 5, "height": 4,
+   "data": [1, 1, 1, 1, 1,
+            3, 2, 2, 2, 1,
+            1, 2, 2, 4, 1,
+            1, 1, 1, 1, 1]},
+  {"type": "objectgroup", "name": "spawns", "objects": [
+   {"id": 1, "type": "player", "x": 24, "y": 24, "width": 0, "height": 0, "point": true},
+   {"id": 2, "class": "monster", "gid": 2, "x": 32, "y": 48, "width": 16, "height": 16,
+    "properties": [{"name": "hit_points", "type": "int", "value": 4}]},
+   {"id": 3, "class": "torch", "x": 32, "y": 16, "width": 16, "height": 16,
+    "properties": [{"name": "color", "type": "color", "value": "#ff0080ff"}]}
+  ]},
+  {"type": "group", "name": "loft", "properties": [{"name": "floor", "type": "int", "value": 1}],
+   "layers": [
+    {"type": "tilelayer", "name": "loft floor", "x": 1, "y": 1, "width": 2, "height": 1,
+     "data": [2, 2]},
+    {"type": "objectgroup", "name": "loft spawns", "objects": [
+     {"id": 4, "class": "familiar", "x": 24, "y": 24}
+    ]}
+   ]}
+ ]
+}"##;
+
+    #[test]
+    fn imports_tiles_and_objects() {
+        let world = load(MAP).unwrap();
+        assert_eq!(world.walls.len(), 5 + 1 + 2 + 5);
+        assert!(!world.walls.contains(&(0, 2, 0)));
+        assert_eq!(world.ground_at(0, 2, 0), Some(Ground::Grass));
+        assert_eq!(world.ground_at(1, 1, 0), Some(Ground::Dirt));
+        // undefined tile
+        assert_eq!(world.ground_at(3, 1, 0), None);
+        assert_eq!(
+            &world.spawns[..],
+            &[
+                Spawn::Player {
+                    tile: (1, 2, 0),
+                    hit_points: PLAYER_HIT_POINTS
+                },
+                Spawn::Monster {
+                    tile: (2, 1, 0),
+                    hit_points: 4
+                },
+                Spawn::Familiar { tile: (1, 2, 1) },
+            ][..]
+        );
+        assert_eq!(world.ground_at(2, 2, 1), Some(Ground::Dirt));
+        let color = world.lights[0].color;
+        assert!((color.blue - 1.0).abs() < 1e-6 && (color.green - 128.0 / 255.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn errors_tell_what_is_wrong() {
+        let off_the_loft = MAP.replace(r#""x": 24, "y": 24}"#, r#""x": 24, "y": 40}"#);
+        let error = load(&off_the_loft).unwrap_err();
+        assert_eq!(
+            error,
+            "object 4 (`familiar`): off the map at 1, 1 of floor 1"
+        );
+        let strong = MAP.replace(r#""value": 4}"#, r#""value": 16}"#);
+        assert_eq!(
+            load(&strong).unwrap_err(),
+            "object 2 (`monster`): hit_points has to be from 1 to 8"
+        );
+        let broken = MAP.replace("\"width\": 5,", "\"width\": 5");
+        assert!(load(&broken).unwrap_err().starts_with("2:"));
+    }
+}
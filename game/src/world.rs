@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use super::*;
 
@@ -8,6 +8,7 @@ pub struct WorldDiff {
     pub z: f32,
 }
 
+/// `z` is the floor: every floor is a single layer of chunks
 #[derive(Default, Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ChunkIdx {
     pub x: i32,
@@ -65,11 +66,125 @@ impl WorldPosition {
 
 #[derive(Debug)]
 pub struct World {
-    middle: i32,
     pub tile_side: f32,  //in meters
     pub chunk_side: f32, //in meters
+    /// from one floor to the next, in meters
+    pub floor_height: f32,
     chunks: HashMap<ChunkIdx, Chunk>,
     pub walls: Vec<(i32, i32, i32)>,
+    pub stairs: Vec<Stairs>,
+    pub lights: Vec<PointLight>,
+    rooms: Vec<Room>,
+    pub spawns: Vec<Spawn>,
+}
+
+/// Entity `GameState::start` puts on the tile `tile`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Spawn {
+    Player {
+        tile: (i32, i32, i32),
+        hit_points: usize,
+    },
+    Monster {
+        tile: (i32, i32, i32),
+        hit_points: usize,
+    },
+    Familiar {
+        tile: (i32, i32, i32),
+    },
+    Prop {
+        tile: (i32, i32, i32),
+        prop: Prop,
+    },
+}
+
+impl Spawn {
+    pub fn tile(&self) -> (i32, i32, i32) {
+        match *self {
+            Spawn::Player { tile, .. }
+            | Spawn::Monster { tile, .. }
+            | Spawn::Familiar { tile }
+            | Spawn::Prop { tile, .. } => tile,
+        }
+    }
+}
+
+/// Scenery standing on a floor tile, in the way like walls
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Prop {
+    Tree,
+    Rock,
+}
+
+impl Prop {
+    pub const ALL: [Prop; 2] = [Prop::Tree, Prop::Rock];
+
+    /// As levels spell it
+    pub fn name(self) -> &'static str {
+        match self {
+            Prop::Tree => "tree",
+            Prop::Rock => "rock",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PointLight {
+    pub p: WorldPosition,
+    /// above the ground, in meters
+    pub z: f32,
+    pub color: Color,
+    /// in meters, nothing is lit past it
+    pub radius: f32,
+}
+
+/// Tiles `min..max` (exclusive) of floor `z` sharing an ambient light level and a floor
+#[derive(Copy, Clone, Debug)]
+struct Room {
+    min: (i32, i32),
+    max: (i32, i32),
+    z: i32,
+    ambient: Color,
+    ground: Ground,
+}
+
+impl Room {
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        z == self.z && x >= self.min.0 && y >= self.min.1 && x < self.max.0 && y < self.max.1
+    }
+}
+
+/// How `Stairs` are climbed
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StairsKind {
+    /// Walked up the screen, rising one floor along the way
+    Stairs,
+    /// Stepped onto, changing floors at once
+    Ladder,
+}
+
+/// Leads from floor `z` of the tile at `x`, `y` to floor `z + climb`. Stairs take `length`
+/// tiles up the screen from that tile; ladders a single one
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stairs {
+    pub kind: StairsKind,
+    pub tile: (i32, i32, i32),
+    pub length: i32,
+    pub climb: i32,
+}
+
+/// What the floor of a tile is covered with
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Ground {
+    Grass,
+    Dirt,
+}
+
+/// Tile of a hand made map
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MapTile {
+    pub ground: Ground,
+    pub wall: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -102,104 +217,257 @@ impl Chunk {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub struct Tile {
-    pub kind: TileKind,
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub enum TileKind {
-    Wall,
-    Ground,
-}
+const AMBIENT_OUTDOORS: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+    alpha: 1.0,
+};
+const AMBIENT_DUNGEON: Color = Color {
+    red: 0.35,
+    green: 0.35,
+    blue: 0.45,
+    alpha: 1.0,
+};
+pub const PLAYER_HIT_POINTS: usize = 3;
+pub const MONSTER_HIT_POINTS: usize = 2;
+/// Each is drawn as its own piece of the entity, next to its sprites
+pub const MAX_HIT_POINTS: usize = 8;
+/// In meters
+pub const TORCH_HEIGHT: f32 = 2.0;
+pub const TORCH_RADIUS: f32 = 9.0;
+/// Of a flight, in tiles
+pub const STAIRS_LENGTH: i32 = 2;
+pub const MAX_STAIRS_LENGTH: i32 = 8;
+
+pub const TORCHES: [Color; TORCH_COLORS] = [
+    Color {
+        red: 1.0,
+        green: 0.7,
+        blue: 0.4,
+        alpha: 1.0,
+    },
+    Color {
+        red: 0.5,
+        green: 0.7,
+        blue: 1.0,
+        alpha: 1.0,
+    },
+    Color {
+        red: 0.6,
+        green: 1.0,
+        blue: 0.5,
+        alpha: 1.0,
+    },
+];
 
 impl World {
-    pub fn initial_monster(&self) -> WorldPosition {
-        let center = self.position_at_tile(17 / 2 + 1, 9 / 2 + 1, 0);
-        self.map_into_chunk_space(center, V2::default())
-    }
-
+    /// Where the first player spawns, or the origin
     pub fn initial_player(&self) -> WorldPosition {
-        let center = self.position_at_tile(17 / 2, 9 / 2, 0);
-        self.map_into_chunk_space(center, V2::default())
-    }
-
-    pub fn initial_camera(&self) -> WorldPosition {
-        let center = WorldPosition {
-            // abs: ChunkIdx::new(self.middle + 17 / 2, self.middle + 9 / 2, self.middle + 0),
-            offset: V2::new(1.5, 1.5),
-            abs: ChunkIdx::default(),
-        };
-        self.map_into_chunk_space(center, V2::default())
+        let player = self.spawns.iter().find_map(|s| match s {
+            Spawn::Player { tile, .. } => Some(*tile),
+            _ => None,
+        });
+        let (x, y, z) = player.unwrap_or((0, 0, 0));
+        self.position_at_tile(x, y, z)
     }
 
+    /// Without a single room
     pub fn new() -> Self {
         let tile_side = 1.4;
         let chunk_side = tile_side * TILES_PER_CHUNK as f32;
-        let mut result = Self {
-            // middle: std::i16::MAX as i32 / (TILES_PER_CHUNK as i32 * 2),
-            middle: 0,
+        Self {
             tile_side,
             chunk_side,
+            floor_height: 3.0,
             chunks: HashMap::new(),
             walls: vec![],
-        };
+            stairs: vec![],
+            lights: vec![],
+            rooms: vec![],
+            spawns: vec![],
+        }
+    }
 
-        result.dummy_world();
+    /// The dungeon of `seed`, see `Dungeon::generate`
+    pub fn generate(seed: u32) -> Self {
+        let mut result = Self::new();
+        result.build(&Dungeon::generate(seed, &DungeonSpec::default()));
         result
     }
 
-    pub fn debug_stuff(&self) {
+    pub fn debug_stuff(&self, host_api: &dyn HostApi) {
         if self.chunks.len() != 0 {
-            println!("Chunk count: {}", self.chunks.len());
-        // for (_, c) in &self.chunks {
-        //     println!("\tcount: {}\tchunk:{:?}", c.entities.len(), c);
-        // }
+            debug!(host_api, "world", "chunk count: {}", self.chunks.len());
+            for (idx, c) in &self.chunks {
+                trace!(
+                    host_api,
+                    "world",
+                    "chunk {:?}: {} entities",
+                    idx,
+                    c.entities.len()
+                );
+            }
         } else {
-            println!("empty");
+            debug!(host_api, "world", "empty");
+        }
+    }
+
+    /// Ambient light of the room `p` is in, rooms not listed are outdoors
+    pub fn ambient_at(&self, p: WorldPosition) -> Color {
+        let (x, y, z) = self.tile_at(p);
+        self.rooms
+            .iter()
+            .find(|r| r.contains(x, y, z))
+            .map_or(AMBIENT_OUTDOORS, |r| r.ambient)
+    }
+
+    /// Tile `p` is on, the other way around from `position_at_tile`
+    pub fn tile_at(&self, p: WorldPosition) -> (i32, i32, i32) {
+        let tile = |abs: i32, offset: f32| {
+            ((abs as f32 * self.chunk_side + offset) / self.tile_side).round() as i32
+        };
+        (
+            tile(p.abs.x, p.offset.x()),
+            tile(p.abs.y, p.offset.y()),
+            p.abs.z,
+        )
+    }
+
+    /// Every tile with a floor or a wall, one by one rather than as rooms
+    pub fn map_tiles(&self) -> BTreeMap<(i32, i32, i32), MapTile> {
+        let mut result = BTreeMap::new();
+        for room in &self.rooms {
+            for y in room.min.1..room.max.1 {
+                for x in room.min.0..room.max.0 {
+                    let tile = MapTile {
+                        ground: room.ground,
+                        wall: false,
+                    };
+                    result.entry((x, y, room.z)).or_insert(tile);
+                }
+            }
+        }
+        for &wall in &self.walls {
+            let tile = result.entry(wall).or_insert(MapTile {
+                ground: Ground::Dirt,
+                wall: true,
+            });
+            tile.wall = true;
+        }
+        result
+    }
+
+    /// Floor of the tile at `abs_x`, `abs_y` on floor `abs_z`; nothing outside of the rooms
+    pub fn ground_at(&self, abs_x: i32, abs_y: i32, abs_z: i32) -> Option<Ground> {
+        self.rooms
+            .iter()
+            .find(|r| r.contains(abs_x, abs_y, abs_z))
+            .map(|r| r.ground)
+    }
+
+    /// Whether any tile of `min..max` (exclusive) on floor `abs_z` has a floor
+    pub fn has_ground(&self, min: (i32, i32), max: (i32, i32), abs_z: i32) -> bool {
+        self.rooms.iter().any(|r| {
+            r.z == abs_z && r.min.0 < max.0 && r.min.1 < max.1 && min.0 < r.max.0 && min.1 < r.max.1
+        })
+    }
+
+    /// Walls, floors and torches of every room of `dungeon`, and where to spawn what
+    fn build(&mut self, dungeon: &Dungeon) {
+        for plan in &dungeon.rooms {
+            let (min_x, min_y, z) = plan.min();
+            let max = (min_x + ROOM_WIDTH, min_y + ROOM_HEIGHT);
+            let ground = if plan.outdoors {
+                Ground::Grass
+            } else {
+                Ground::Dirt
+            };
+            self.add_room((min_x, min_y), max, z, ground);
+            if !plan.outdoors {
+                let center = (min_x + ROOM_WIDTH / 2, min_y + ROOM_HEIGHT / 2, z);
+                self.add_torch(center, TORCH_HEIGHT, TORCHES[plan.torch], TORCH_RADIUS);
+            }
+            self.walls.extend(plan.walls());
+        }
+        self.stairs.extend_from_slice(&dungeon.stairs);
+        let (player, hit_points) = (dungeon.player, PLAYER_HIT_POINTS);
+        self.spawns.push(Spawn::Player {
+            tile: player,
+            hit_points,
+        });
+        for &tile in &dungeon.monsters {
+            let hit_points = MONSTER_HIT_POINTS;
+            self.spawns.push(Spawn::Monster { tile, hit_points });
+        }
+        for &tile in &dungeon.familiars {
+            self.spawns.push(Spawn::Familiar { tile });
         }
     }
 
-    fn dummy_world(&mut self) {
-        let tiles_per_width = 17;
-        let tiles_per_height = 9;
-
-        for screen_y in 0..3 {
-            for screen_x in 0..3 {
-                for tile_y in 0..tiles_per_height {
-                    for tile_x in 0..tiles_per_width {
-                        let abs_x = screen_x * tiles_per_width + tile_x;
-                        let abs_y = screen_y * tiles_per_height + tile_y;
-
-                        let kind = {
-                            if tile_x == 0 || tile_x == tiles_per_width - 1 {
-                                if tile_y == 4 {
-                                    TileKind::Ground
-                                } else {
-                                    TileKind::Wall
-                                }
-                            } else if tile_y == 0 || tile_y == tiles_per_height - 1 {
-                                if tile_x == 8 {
-                                    TileKind::Ground
-                                } else {
-                                    TileKind::Wall
-                                }
-                            } else {
-                                TileKind::Ground
-                            }
-                        };
-                        let abs_x = abs_x + self.middle;
-                        let abs_y = abs_y + self.middle;
-                        let abs_z = 0 + self.middle;
-                        if kind == TileKind::Wall {
-                            self.walls.push((abs_x, abs_y, abs_z));
-                        }
-                    }
+    /// Tiles `min..max` (exclusive) of floor `z`. Grass is outdoors, dirt is lit by torches
+    pub fn add_room(&mut self, min: (i32, i32), max: (i32, i32), z: i32, ground: Ground) {
+        let ambient = match ground {
+            Ground::Grass => AMBIENT_OUTDOORS,
+            Ground::Dirt => AMBIENT_DUNGEON,
+        };
+        self.rooms.push(Room {
+            min,
+            max,
+            z,
+            ambient,
+            ground,
+        });
+    }
+
+    /// Tiles from `first` on to the east, `None` being nothing. Every run of the same ground
+    /// becomes a room one tile high
+    pub fn add_row(&mut self, first: (i32, i32, i32), tiles: &[Option<MapTile>]) {
+        let (min_x, y, z) = first;
+        let mut run: Option<(i32, Ground)> = None;
+        for (i, tile) in tiles.iter().chain(std::iter::once(&None)).enumerate() {
+            let x = min_x + i as i32;
+            let ground = tile.map(|t| t.ground);
+            if let Some((start, run_ground)) = run {
+                if Some(run_ground) != ground {
+                    self.add_room((start, y), (x, y + 1), z, run_ground);
+                    run = None;
                 }
             }
+            if let (None, Some(ground)) = (run, ground) {
+                run = Some((x, ground));
+            }
+            if tile.map_or(false, |t| t.wall) {
+                self.walls.push((x, y, z));
+            }
+        }
+    }
+
+    /// Both ends, the one on floor `z + climb` leading back
+    pub fn add_stairs(&mut self, kind: StairsKind, tile: (i32, i32, i32), length: i32, climb: i32) {
+        let (x, y, z) = tile;
+        for &(z, climb) in &[(z, climb), (z + climb, -climb)] {
+            self.stairs.push(Stairs {
+                kind,
+                tile: (x, y, z),
+                length,
+                climb,
+            });
         }
     }
 
+    /// `height` and `radius` in meters
+    pub fn add_torch(&mut self, tile: (i32, i32, i32), height: f32, color: Color, radius: f32) {
+        let (x, y, z) = tile;
+        let p = self.position_at_tile(x, y, z);
+        self.lights.push(PointLight {
+            p,
+            z: height,
+            color,
+            radius,
+        });
+    }
+
     pub fn chunk(&self, idx: ChunkIdx) -> Option<&Chunk> {
         self.chunks.get(&idx)
     }
@@ -208,18 +476,14 @@ impl World {
         let epsilon = 0.0001;
         let min = -0.5 * self.chunk_side - epsilon;
         let max = 0.5 * self.chunk_side + epsilon;
-        let result = rel >= min && rel <= max;
-        if !result {
-            println!("min: {}, max: {}, current: {}", min, max, rel);
-        }
-        result
+        rel >= min && rel <= max
     }
 
     fn same_chunk(&self, a: WorldPosition, b: WorldPosition) -> bool {
-        assert!(self.is_canonical(a.offset.x()));
-        assert!(self.is_canonical(a.offset.y()));
-        assert!(self.is_canonical(b.offset.x()));
-        assert!(self.is_canonical(b.offset.y()));
+        assert!(self.is_canonical(a.offset.x()), "not canonical: {:?}", a);
+        assert!(self.is_canonical(a.offset.y()), "not canonical: {:?}", a);
+        assert!(self.is_canonical(b.offset.x()), "not canonical: {:?}", b);
+        assert!(self.is_canonical(b.offset.y()), "not canonical: {:?}", b);
 
         a.abs == b.abs
     }
@@ -228,7 +492,7 @@ impl World {
         let offset = (rel / self.chunk_side).round();
         let new_pos = pos + offset as i32;
         let new_rel = rel - offset * self.chunk_side;
-        assert!(self.is_canonical(new_rel));
+        assert!(self.is_canonical(new_rel), "not canonical: {}", new_rel);
         (new_pos, new_rel)
     }
 
@@ -244,11 +508,12 @@ impl World {
         result
     }
 
+    /// Center of the tile at `abs_x`, `abs_y` on floor `abs_z`
     pub fn position_at_tile(&self, abs_x: i32, abs_y: i32, abs_z: i32) -> WorldPosition {
         let chunk_idx = ChunkIdx {
             x: abs_x / TILES_PER_CHUNK as i32,
             y: abs_y / TILES_PER_CHUNK as i32,
-            z: abs_z / TILES_PER_CHUNK as i32,
+            z: abs_z,
         };
         let x = (abs_x - (chunk_idx.x * TILES_PER_CHUNK as i32)) as f32 * self.tile_side;
         let y = (abs_y - (chunk_idx.y * TILES_PER_CHUNK as i32)) as f32 * self.tile_side;
@@ -259,6 +524,7 @@ impl World {
         self.map_into_chunk_space(pos, V2::default())
     }
 
+    /// `z` is how far above `b`'s floor `a`'s floor is, in meters
     pub fn substract(&self, a: WorldPosition, b: WorldPosition) -> WorldDiff {
         let x = a.abs.x - b.abs.x;
         let y = a.abs.y - b.abs.y;
@@ -267,7 +533,7 @@ impl World {
 
         WorldDiff {
             xy: self.chunk_side * xy + (a.offset - b.offset),
-            z: self.chunk_side * z as f32,
+            z: self.floor_height * z as f32,
         }
     }
 
@@ -7,11 +7,91 @@ pub trait HostApi {
         println!("{}", val)
     }
 
+    /// Whether a message for `tag` at `level` would be kept. Lets callers skip formatting
+    fn log_enabled(&self, level: LogLevel, _tag: &str) -> bool {
+        level <= LogLevel::Info
+    }
+
+    /// Filtering and output (stderr, log file...) are up to the host
+    fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        if self.log_enabled(level, tag) {
+            eprintln!("[{}] {}: {}", level, tag, msg)
+        }
+    }
+
+    /// Changes the level of a single subsystem at runtime
+    fn set_log_level(&self, _tag: &str, _level: LogLevel) {
+    }
+
+    /// Most recent log lines, oldest first
+    fn recent_logs(&self, _count: usize) -> Vec<String> {
+        vec![]
+    }
+
+    /// Runs `job` once for every index in `0..count`, possibly on other threads.
+    /// Returns once all of them are done
+    fn parallel_for(&self, count: usize, job: &(dyn Fn(usize) + Sync)) {
+        for i in 0..count {
+            job(i);
+        }
+    }
+
     fn update_canvas(&mut self, buffer: &[u8], pitch: usize);
 
     fn generate_audio(&mut self);
 
     fn load_bmp(&self, path: &str) -> Bitmap;
+
+    /// Paths are relative to the host's data root
+    fn read_file(&self, path: &str) -> std::io::Result<Vec<u8>>;
+
+    /// Either the whole of `contents` ends up in `path`, or the old file is kept
+    fn write_file(&self, path: &str, contents: &[u8]) -> std::io::Result<()>;
+
+    /// Entry names in `path`, sorted
+    fn list_dir(&self, path: &str) -> std::io::Result<Vec<String>>;
+
+    /// Writes a B G R A buffer (top row first) as a 24-bit bmp, alpha is dropped
+    fn save_bmp(
+        &self, path: &str, buffer: &[u8], width: usize, height: usize, pitch: usize,
+    ) -> std::io::Result<()>;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!("unknown log level: {}", s)),
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -21,12 +101,60 @@ pub struct InputState {
     pub left: bool,
     pub right: bool,
     pub sword: bool,
+    pub debug: bool,
+    pub next_entity: bool,
+    pub screenshot: bool,
+    pub record: bool,
+    pub zoom_in: bool,
+    pub zoom_out: bool,
+    pub edit: bool,
+    pub save: bool,
+    pub increase: bool,
+    pub decrease: bool,
+    pub floor_up: bool,
+    pub floor_down: bool,
+    pub log_tag: bool,
+    pub log_less: bool,
+    pub log_more: bool,
+
+    /// Mouse position in `OffscreenBuffer` pixels
+    pub mouse_x: i32,
+    pub mouse_y: i32,
+    pub mouse_left: bool,
+    pub mouse_right: bool,
 }
 
 pub struct Input {
     pub old: InputState,
     pub new: InputState,
     pub time_per_frame: f32,
+
+    /// Wall-clock duration of the previous frame, in seconds
+    pub frame_time: f32,
+}
+
+impl Input {
+    /// `true` only on the frame the button went down
+    pub fn pressed(&self, button: impl Fn(&InputState) -> bool) -> bool {
+        button(&self.new) && !button(&self.old)
+    }
+}
+
+/// Memory owned by the host and handed to the game on init. It survives game reloads.
+/// `permanent` holds the game state and the heap it allocates from, `transient` is scratch
+/// space the game resets every frame
+pub struct GameMemory {
+    pub permanent: Box<[u8]>,
+    pub transient: Box<[u8]>,
+}
+
+impl GameMemory {
+    pub fn new(permanent_size: usize, transient_size: usize) -> Self {
+        Self {
+            permanent: vec![0; permanent_size].into_boxed_slice(),
+            transient: vec![0; transient_size].into_boxed_slice(),
+        }
+    }
 }
 
 pub struct Bitmap {